@@ -1,8 +1,106 @@
 use format::*;
+use snapshot::{OverheadPolicy, ProfileSnapshot};
+use std::time::Duration;
 
 /// A print that actually does nothing. Exists for the `disabled`-feature.
 pub fn print() {}
 /// A print that actually does nothing. Exists for the `disabled`-feature.
 pub fn print_with_format(format: FormattingOptions, decimals: u32) {}
+/// A print that actually does nothing. Exists for the `disabled`-feature.
+pub fn print_with_options(options: &ReportOptions) {}
+/// A frame mark that actually does nothing. Exists for the `disabled`-feature.
+pub fn frame_mark() {}
+/// An add_aggregation that actually does nothing. Exists for the `disabled`-feature.
+pub fn add_aggregation<T, F>(name: T, aggregation: F)
+where
+    T: Into<String>,
+    F: Fn(&[u64]) -> f64 + Send + Sync + 'static,
+{
+}
+/// A remove_aggregation that actually does nothing. Exists for the `disabled`-feature.
+pub fn remove_aggregation(name: &str) {}
+/// A capture_next that actually does nothing. Exists for the `disabled`-feature.
+pub fn capture_next(iterations: usize, print_when_done: bool) {}
 /// A reset that actually does nothing. Exists for the `disabled`-feature.
 pub fn reset() {}
+/// A disable_scope that does nothing. Exists for the `disabled`-feature.
+pub fn disable_scope(name: &str) {}
+/// An enable_scope that does nothing. Exists for the `disabled`-feature.
+pub fn enable_scope(name: &str) {}
+/// A set_overhead_policy that does nothing. Exists for the `disabled`-feature.
+pub fn set_overhead_policy(policy: OverheadPolicy) {}
+/// An overhead_policy that always returns the default policy. Exists
+/// for the `disabled`-feature.
+pub fn overhead_policy() -> OverheadPolicy {
+    OverheadPolicy::Inclusive
+}
+/// A ratio that always returns `None`. Exists for the `disabled`-feature.
+pub fn ratio(path_a: &[&str], path_b: &[&str]) -> Option<f64> {
+    None
+}
+/// A tree_hash that always returns 0. Exists for the `disabled`-feature.
+pub fn tree_hash() -> u64 {
+    0
+}
+/// A reset_interval that actually does nothing. Exists for the `disabled`-feature.
+pub fn reset_interval() {}
+/// A worst_frame_time that always returns `None`. Exists for the `disabled`-feature.
+pub fn worst_frame_time() -> Option<Duration> {
+    None
+}
+/// A critical_path that always returns an empty `Vec`. Exists for the `disabled`-feature.
+pub fn critical_path() -> Vec<(Vec<String>, f64)> {
+    Vec::new()
+}
+/// An estimated_savings that always returns zero. Exists for the `disabled`-feature.
+pub fn estimated_savings(path: &[&str]) -> Duration {
+    Duration::new(0, 0)
+}
+/// A current_path that always returns an empty `Vec`. Exists for the `disabled`-feature.
+pub fn current_path() -> Vec<String> {
+    Vec::new()
+}
+/// A snapshot that is always empty. Exists for the `disabled`-feature.
+pub fn snapshot() -> ProfileSnapshot {
+    ProfileSnapshot::default()
+}
+/// A thread_snapshots that always returns an empty `Vec`. Exists for the `disabled`-feature.
+pub fn thread_snapshots() -> Vec<ProfileSnapshot> {
+    Vec::new()
+}
+/// A worst_sample that always returns `None`. Exists for the `disabled`-feature.
+pub fn worst_sample() -> Option<(Vec<String>, u64)> {
+    None
+}
+/// A get_ron that always returns an empty `String`. Exists for the `disabled`-feature.
+pub fn get_ron() -> String {
+    String::new()
+}
+/// A get_formatted_string that always returns an empty `String`. Exists for the `disabled`-feature.
+pub fn get_formatted_string(ops: FormattingOptions, decimals: usize) -> String {
+    String::new()
+}
+/// A get_formatted_string_with_options that always returns an empty `String`. Exists for the `disabled`-feature.
+pub fn get_formatted_string_with_options(options: &ReportOptions) -> String {
+    String::new()
+}
+/// A get_formatted_snapshot that always returns an empty `String`. Exists for the `disabled`-feature.
+pub fn get_formatted_snapshot(snapshot: &ProfileSnapshot, options: &ReportOptions) -> String {
+    String::new()
+}
+/// A coverage that always returns 0. Exists for the `disabled`-feature.
+pub fn coverage() -> f64 {
+    0.0
+}
+/// A recent_frame_times that always returns an empty `Vec`. Exists for the `disabled`-feature.
+pub fn recent_frame_times(count: usize) -> Vec<Duration> {
+    Vec::new()
+}
+/// A calibrate_overhead that always returns 0. Exists for the `disabled`-feature.
+pub fn calibrate_overhead() -> Duration {
+    Duration::new(0, 0)
+}
+/// A calibrated_overhead that always returns `None`. Exists for the `disabled`-feature.
+pub fn calibrated_overhead() -> Option<Duration> {
+    None
+}
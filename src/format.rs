@@ -1,6 +1,10 @@
 //! Formats for making the formatted outputs (see
 //! [`get_formatted_string`](../fn.get_formatted_string.html))
 
+use std::time::Duration;
+
+use snapshot::ProfileSnapshot;
+
 /// A very streamlined format. This is the default format.
 ///
 /// ```text
@@ -121,3 +125,752 @@ pub struct FormattingOptions {
     /// See the reference-print, `turning_ending_branch` is represented by ",,,,"
     pub turning_ending_branch: &'static str,
 }
+
+/// Defines how the percentages in the formatted output are
+/// calculated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Percentages {
+    /// The percentage is the scope's total time divided by its
+    /// parent's total time. Scopes which are occasionally very slow
+    /// will weigh a lot. This is the default.
+    Totals,
+    /// The percentage is calculated separately for every root
+    /// iteration (every time a root-level scope finishes, or between
+    /// [`frame_mark`](../fn.frame_mark.html)s if they're used), and
+    /// then averaged. This shows the composition of a typical iteration,
+    /// eg. a typical frame.
+    PerIteration,
+}
+
+/// Defines which scopes show their throughput, see
+/// [`ReportOptions::ops_per_second`](struct.ReportOptions.html#structfield.ops_per_second).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OpsPerSecond {
+    /// No scope shows its throughput. This is the default.
+    Off,
+    /// Every scope shows its throughput.
+    All,
+    /// The scopes with these names show their throughput.
+    Scopes(Vec<String>),
+}
+
+/// Defines what the scopes are ranked by, see
+/// [`ReportOptions::show_rank`](struct.ReportOptions.html#structfield.show_rank).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RankBy {
+    /// The scope's total time, minus the total times of the scopes
+    /// under it. This is the default.
+    SelfTime,
+    /// The scope's total time.
+    Total,
+}
+
+/// Defines what goes into the formatted string, and how.
+///
+/// ```
+/// use stperf::format::{self, Percentages, ReportOptions};
+///
+/// let options = ReportOptions {
+///     format: format::COMPATIBLE,
+///     percentages: Percentages::PerIteration,
+///     ..Default::default()
+/// };
+/// stperf::print_with_options(&options);
+/// ```
+#[derive(Clone)]
+pub struct ReportOptions {
+    /// The format used for the tree, see the [`format`](index.html)
+    /// module.
+    pub format: FormattingOptions,
+    /// The amount of decimals the timings are printed with.
+    pub decimals: usize,
+    /// How the percentages are calculated.
+    pub percentages: Percentages,
+    /// Should a warning be included in the output if the clock's
+    /// resolution is coarse compared to the shortest recorded
+    /// samples? Clocks with a resolution under a microsecond are
+    /// never warned about. The warning is only shown once per
+    /// process.
+    pub clock_warning: bool,
+    /// If set, leaf scopes which take less than this percentage of
+    /// their parent's time are left out of the output. Unlike simply
+    /// hiding them, their time is shown as "absorbed" in the parent's
+    /// row, so it's not lost. Scopes whose children were all absorbed
+    /// can be absorbed as well.
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main"], &[10_000_000])
+    ///     .add_scope(&["main", "update"], &[9_000_000])
+    ///     .add_scope(&["main", "update", "tick"], &[100_000])
+    ///     .add_scope(&["main", "logging"], &[400_000])
+    ///     .add_scope(&["main", "logging", "format"], &[10_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     absorb_below: Some(5.0),
+    ///     decimals: 1,
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     concat!(
+    ///         "╶──┬╼ main       - 100.0%, 10.0 ms/loop, 1 samples, 0.4 ms/loop absorbed\n",
+    ///         "   └───╼ update  -  90.0%,  9.0 ms/loop, 1 samples, 0.1 ms/loop absorbed\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub absorb_below: Option<f64>,
+    /// If true, the percentages of the root-level scopes are their
+    /// share of the total time of all the root-level scopes, so they
+    /// sum up to 100%. Otherwise, each root-level scope is 100% of
+    /// itself, which is the default.
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["update"], &[3_000_000])
+    ///     .add_scope(&["render"], &[1_000_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     shared_root_percentages: true,
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     concat!(
+    ///         "╶───╼ render  -  25.0%,   1 ms/loop, 1 samples\n",
+    ///         "╶───╼ update  -  75.0%,   3 ms/loop, 1 samples\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub shared_root_percentages: bool,
+    /// If true, scopes are sorted by their names. Otherwise, they're
+    /// in the order they were first measured in, which is the
+    /// default.
+    pub sort_by_name: bool,
+    /// If true, a summary line with the
+    /// [`coverage`](../fn.coverage.html) of the root-level scopes is
+    /// added after the tree.
+    pub show_coverage: bool,
+    /// If set, each row gets a bar showing how much of this target
+    /// time (eg. 16.6 ms for 60 FPS) the scope takes per loop. A
+    /// scope that takes longer than the whole target gets a full bar
+    /// which ends in a "!".
+    ///
+    /// ```text
+    /// ╶──┬╼ main          - [##################..] 100.0%, 15 ms/loop, 60 samples
+    ///    ├───╼ physics    - [###########.........]  60.0%,  9 ms/loop, 60 samples
+    ///    └───╼ rendering  - [#######.............]  40.0%,  6 ms/loop, 60 samples
+    /// ```
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use std::time::Duration;
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main"], &[20_000_000])
+    ///     .add_scope(&["main", "physics"], &[12_000_000])
+    ///     .add_scope(&["main", "render"], &[5_000_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     budget_bar: Some(Duration::from_millis(16)),
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     concat!(
+    ///         "╶──┬╼ main        - [####################! 100.0%,  20 ms/loop, 1 samples\n",
+    ///         "   ├───╼ physics  - [###############.....]  60.0%,  12 ms/loop, 1 samples\n",
+    ///         "   └───╼ render   - [######..............]  25.0%,   5 ms/loop, 1 samples\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub budget_bar: Option<Duration>,
+    /// If true, the last line of the output ends in a newline, like
+    /// all the other lines. This is the default. Set this to false
+    /// if the output is placed inside something else, eg. a GUI
+    /// label.
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main"], &[1_000_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     trailing_newline: false,
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     "╶───╼ main  - 100.0%,   1 ms/loop, 1 samples"
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub trailing_newline: bool,
+    /// If set, scopes with names containing this separator are shown
+    /// under a group named by the part before the separator. The
+    /// group's time is the sum of the grouped scopes' times. Scopes
+    /// without the separator stay where they are. For example, with
+    /// `Some('.')`, scopes called "system.physics" and
+    /// "system.render" are shown as "physics" and "render" under
+    /// "system".
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["system.physics"], &[10_000_000, 10_000_000])
+    ///     .add_scope(&["system.render"], &[5_000_000, 5_000_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     group_separator: Some('.'),
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     concat!(
+    ///         "╶──┬╼ system      - 100.0%,  15 ms/loop, 4 samples\n",
+    ///         "   ├───╼ physics  -  66.7%,  10 ms/loop, 2 samples\n",
+    ///         "   └───╼ render   -  33.3%,   5 ms/loop, 2 samples\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub group_separator: Option<char>,
+    /// If true, each row shows how many times the scope was measured
+    /// per root iteration (see
+    /// [`frame_mark`](../fn.frame_mark.html)), eg. "calls/iter: 3-17
+    /// (avg 8.0)". Useful for scopes whose workload varies between
+    /// iterations.
+    ///
+    /// ```
+    /// # #[macro_use] extern crate stperf; #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    ///
+    /// for &enemies in &[1, 3, 2] {
+    ///     perf_measure!("main");
+    ///     for _ in 0..enemies {
+    ///         perf_measure!("enemy");
+    ///     }
+    /// }
+    /// let options = ReportOptions {
+    ///     show_calls_per_iteration: true,
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// let output = stperf::get_formatted_string_with_options(&options);
+    /// let lines: Vec<&str> = output.lines().collect();
+    /// assert!(lines[0].contains(", 3 samples, calls/iter: 1"));
+    /// assert!(lines[1].contains(", 6 samples, calls/iter: 1-3 (avg 2.0)"));
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub show_calls_per_iteration: bool,
+    /// If true, the wall-clock times the session started and the
+    /// output was formatted at are shown as UTC timestamps in a
+    /// header line, eg. "session: 2024-03-01T12:00:00.000Z -
+    /// 2024-03-01T12:00:05.250Z".
+    /// Useful for matching a saved profile with other logs. The
+    /// session starts at the first measurement after the last
+    /// [`reset`](../fn.reset.html).
+    pub show_wall_clock: bool,
+    /// If true, only the samples of the slowest root iteration (see
+    /// [`frame_mark`](../fn.frame_mark.html)) are shown, instead of
+    /// all of them. The slowest iteration is the one with the most
+    /// time spent in root-level scopes. This shows where the time
+    /// went during eg. the worst frame, which the averages of all
+    /// the frames tend to smooth over.
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main"], &[2_000_000, 8_000_000, 4_000_000])
+    ///     .add_scope(&["main", "physics"], &[1_000_000, 6_000_000, 1_000_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     worst_iteration: true,
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     concat!(
+    ///         "worst iteration: 1 (8 ms)\n",
+    ///         "╶──┬╼ main        - 100.0%,   8 ms/loop, 1 samples\n",
+    ///         "   └───╼ physics  -  75.0%,   6 ms/loop, 1 samples\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub worst_iteration: bool,
+    /// If set, this many of the slowest scopes, as defined by
+    /// `rank_by`, get their rank prepended to their names, eg. "#1
+    /// physics". Handy for referring to rows when discussing a
+    /// profile.
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main"], &[10_000_000])
+    ///     .add_scope(&["main", "physics"], &[6_000_000])
+    ///     .add_scope(&["main", "physics", "collisions"], &[5_000_000])
+    ///     .add_scope(&["main", "rendering"], &[3_000_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     show_rank: Some(2),
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     concat!(
+    ///         "╶──┬╼ main                 - 100.0%,  10 ms/loop, 1 samples\n",
+    ///         "   ├──┬╼ physics           -  60.0%,   6 ms/loop, 1 samples\n",
+    ///         "   │  └───╼ #1 collisions  -  83.3%,   5 ms/loop, 1 samples\n",
+    ///         "   └───╼ #2 rendering      -  30.0%,   3 ms/loop, 1 samples\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub show_rank: Option<usize>,
+    /// What the scopes are ranked by for `show_rank`.
+    pub rank_by: RankBy,
+    /// If set, this is drawn in every ancestor's column where the
+    /// tree has no branch, so deep rows are easier to follow back to
+    /// their ancestors. A lighter glyph than the format's
+    /// `continuing_branch`, like "┆", keeps the guides apart from the
+    /// branches.
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main"], &[3_000_000])
+    ///     .add_scope(&["main", "update"], &[2_000_000])
+    ///     .add_scope(&["main", "update", "physics"], &[1_000_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     indentation_guide: Some("┆"),
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     concat!(
+    ///         "╶──┬╼ main           - 100.0%,   3 ms/loop, 1 samples\n",
+    ///         "   └──┬╼ update      -  66.7%,   2 ms/loop, 1 samples\n",
+    ///         "   ┆  └───╼ physics  -  50.0%,   1 ms/loop, 1 samples\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub indentation_guide: Option<&'static str>,
+    /// If true, each row shows the difference between the scope's
+    /// times in the last two root iterations it was measured in, eg.
+    /// "last delta: +1.25 ms", to catch the exact frame a scope's time
+    /// changed in. Handy in live overlays that are formatted every
+    /// frame.
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main"], &[10_000_000, 12_500_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     show_last_delta: true,
+    ///     decimals: 2,
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     "╶───╼ main  - 100.0%, 11.25 ms/loop, 2 samples, last delta: +2.50 ms\n"
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    ///
+    /// Rows made up of several scopes, like the groups of
+    /// `group_separator`, show the change of their total:
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["system.physics"], &[4_000_000, 6_000_000])
+    ///     .add_scope(&["system.render"], &[3_000_000, 3_000_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     show_last_delta: true,
+    ///     group_separator: Some('.'),
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     concat!(
+    ///         "╶──┬╼ system      - 100.0%,   8 ms/loop, 4 samples, last delta: +2 ms\n",
+    ///         "   ├───╼ physics  -  62.5%,   5 ms/loop, 2 samples, last delta: +2 ms\n",
+    ///         "   └───╼ render   -  37.5%,   3 ms/loop, 2 samples, last delta: +0 ms\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub show_last_delta: bool,
+    /// If true, parts of the output are colored with ANSI escape
+    /// codes: a `show_last_delta` or a difference to the `baseline`
+    /// that went up is red, and one that went down is green.
+    pub ansi_colors: bool,
+    /// If true, each thread's measurements are shown as their own
+    /// tree, under a header with the thread's name, instead of
+    /// merging them into one tree. See
+    /// [`thread_snapshots`](../fn.thread_snapshots.html).
+    ///
+    /// With `rank_changes_only`, each thread's ranking is compared to
+    /// that thread's previous ranking:
+    ///
+    /// ```
+    /// # #[macro_use] extern crate stperf; #[cfg(not(feature = "disabled"))] fn main() {
+    /// use std::thread;
+    /// use stperf::format::ReportOptions;
+    ///
+    /// for name in &["physics", "render"] {
+    ///     thread::Builder::new()
+    ///         .name(name.to_string())
+    ///         .spawn(|| {
+    ///             perf_measure!("step");
+    ///         })
+    ///         .unwrap()
+    ///         .join()
+    ///         .unwrap();
+    /// }
+    /// let options = ReportOptions {
+    ///     per_thread: true,
+    ///     rank_changes_only: true,
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_string_with_options(&options),
+    ///     "thread: physics\n#1 step (new)\nthread: render\n#1 step (new)\n"
+    /// );
+    /// assert_eq!(
+    ///     stperf::get_formatted_string_with_options(&options),
+    ///     "thread: physics\nthread: render\n"
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub per_thread: bool,
+    /// Which scopes show their throughput, how many times the scope
+    /// could run per second based on its average sample, eg. "9,800
+    /// ops/s". Useful for scopes that process a single unit of work,
+    /// where the rate is more telling than the time.
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::{OpsPerSecond, ReportOptions};
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["process"], &[100_000, 104_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     ops_per_second: OpsPerSecond::Scopes(vec!["process".to_string()]),
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     "╶───╼ process  - 100.0%,   0 ms/loop, 2 samples, 9,804 ops/s\n"
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub ops_per_second: OpsPerSecond,
+    /// If true, instead of the tree, the output lists the scopes whose
+    /// rank changed since the previous output formatted with this
+    /// option, eg. "#1 main/render (was #2)". The scopes are ranked
+    /// as defined by `rank_by`, and only the top `show_rank` scopes
+    /// are followed, if it's set. If nothing changed, the output is
+    /// empty, so it's only worth a look when the hotspots shift.
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let options = ReportOptions {
+    ///     rank_changes_only: true,
+    ///     show_rank: Some(2),
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// let before = SnapshotBuilder::new()
+    ///     .add_scope(&["main", "physics"], &[2_000_000])
+    ///     .add_scope(&["main", "render"], &[1_000_000])
+    ///     .build();
+    /// let after = SnapshotBuilder::new()
+    ///     .add_scope(&["main", "physics"], &[2_000_000])
+    ///     .add_scope(&["main", "render"], &[3_000_000])
+    ///     .build();
+    /// // The first ranking is all new.
+    /// stperf::get_formatted_snapshot(&before, &options);
+    /// assert_eq!(stperf::get_formatted_snapshot(&before, &options), "");
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&after, &options),
+    ///     "#1 main/render (was #2)\n#2 main/physics (was #1)\n"
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub rank_changes_only: bool,
+    /// If set, each scope shows at most this many of the slowest
+    /// scopes under it, and the rest are summed up into a single
+    /// "(… M others)" row after them. Keeps the output readable when
+    /// a scope has lots of children, eg. one for every entity.
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["update"], &[10_000_000])
+    ///     .add_scope(&["update", "player"], &[1_000_000])
+    ///     .add_scope(&["update", "boss"], &[5_000_000])
+    ///     .add_scope(&["update", "rat"], &[2_000_000])
+    ///     .add_scope(&["update", "bat"], &[1_000_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     max_children: Some(2),
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     concat!(
+    ///         "╶──┬╼ update           - 100.0%,  10 ms/loop, 1 samples\n",
+    ///         "   ├───╼ boss          -  50.0%,   5 ms/loop, 1 samples\n",
+    ///         "   ├───╼ rat           -  20.0%,   2 ms/loop, 1 samples\n",
+    ///         "   └───╼ (… 2 others)  -  20.0%,   2 ms/loop, 2 samples\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub max_children: Option<usize>,
+    /// Pairs of scopes whose ratio (see
+    /// [`ratio`](../fn.ratio.html)) is shown in a summary line after
+    /// the tree, eg. "main/render : main/update = 1.50". The scopes
+    /// are given as the paths of their names, like for
+    /// [`ratio`](../fn.ratio.html), and shown with the names joined
+    /// with "/". Handy for keeping an eye on the balance between
+    /// subsystems.
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["render"], &[3_000_000])
+    ///     .add_scope(&["update"], &[2_000_000])
+    ///     .build();
+    /// let path = |name: &str| vec![name.to_string()];
+    /// let options = ReportOptions {
+    ///     ratios: vec![
+    ///         (path("render"), path("update")),
+    ///         (path("audio"), path("update")),
+    ///     ],
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     concat!(
+    ///         "╶───╼ render  - 100.0%,   3 ms/loop, 1 samples\n",
+    ///         "╶───╼ update  - 100.0%,   2 ms/loop, 1 samples\n",
+    ///         "render : update = 1.50\n",
+    ///         "audio : update = no data\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub ratios: Vec<(Vec<String>, Vec<String>)>,
+    /// If set, each row shows how much its ms/loop changed from the
+    /// scope with the same path in this snapshot, eg. "vs baseline:
+    /// +1.00 ms (+50.0%)". Scopes that aren't in the baseline, or
+    /// have no data there, show "vs baseline: new". Handy for
+    /// reviewing an optimization: take a
+    /// [`snapshot`](../fn.snapshot.html) before it, and format the
+    /// measurements after it with the snapshot as the baseline.
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let baseline = SnapshotBuilder::new()
+    ///     .add_scope(&["main", "physics"], &[2_000_000])
+    ///     .add_scope(&["main", "render"], &[4_000_000])
+    ///     .build();
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main", "physics"], &[3_000_000])
+    ///     .add_scope(&["main", "render"], &[2_000_000])
+    ///     .add_scope(&["main", "audio"], &[1_000_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     baseline: Some(baseline),
+    ///     decimals: 2,
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     concat!(
+    ///         "╶──┬╼ main        - no data\n",
+    ///         "   ├───╼ audio    - 100.0%,  1.00 ms/loop, 1 samples, vs baseline: new\n",
+    ///         "   ├───╼ physics  - 100.0%,  3.00 ms/loop, 1 samples, vs baseline: +1.00 ms (+50.0%)\n",
+    ///         "   └───╼ render   - 100.0%,  2.00 ms/loop, 1 samples, vs baseline: -2.00 ms (-50.0%)\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    ///
+    /// The rows made up by the formatter, like the groups of
+    /// [`group_separator`](#structfield.group_separator), are
+    /// compared to the scopes they're made of:
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let baseline = SnapshotBuilder::new()
+    ///     .add_scope(&["system.physics"], &[8_000_000, 8_000_000])
+    ///     .add_scope(&["system.render"], &[5_000_000, 5_000_000])
+    ///     .build();
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["system.physics"], &[10_000_000, 10_000_000])
+    ///     .add_scope(&["system.render"], &[5_000_000, 5_000_000])
+    ///     .build();
+    /// let options = ReportOptions {
+    ///     group_separator: Some('.'),
+    ///     baseline: Some(baseline),
+    ///     ..ReportOptions::deterministic()
+    /// };
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &options),
+    ///     concat!(
+    ///         "╶──┬╼ system      - 100.0%,  15 ms/loop, 4 samples, vs baseline: +2 ms (+15.4%)\n",
+    ///         "   ├───╼ physics  -  66.7%,  10 ms/loop, 2 samples, vs baseline: +2 ms (+25.0%)\n",
+    ///         "   └───╼ render   -  33.3%,   5 ms/loop, 2 samples, vs baseline: +0 ms (+0.0%)\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub baseline: Option<ProfileSnapshot>,
+}
+
+impl Default for ReportOptions {
+    fn default() -> ReportOptions {
+        ReportOptions {
+            format: STREAMLINED,
+            decimals: 0,
+            percentages: Percentages::Totals,
+            clock_warning: true,
+            absorb_below: None,
+            shared_root_percentages: false,
+            sort_by_name: false,
+            show_coverage: false,
+            budget_bar: None,
+            trailing_newline: true,
+            group_separator: None,
+            show_calls_per_iteration: false,
+            show_wall_clock: false,
+            worst_iteration: false,
+            show_rank: None,
+            rank_by: RankBy::SelfTime,
+            indentation_guide: None,
+            show_last_delta: false,
+            ansi_colors: false,
+            per_thread: false,
+            ops_per_second: OpsPerSecond::Off,
+            rank_changes_only: false,
+            max_children: None,
+            ratios: Vec::new(),
+            baseline: None,
+        }
+    }
+}
+
+impl ReportOptions {
+    /// Returns options which make the output depend only on the
+    /// measurement data, eg. for snapshot testing. The scopes are
+    /// sorted by name, and nothing machine-dependent (like the clock
+    /// resolution warning) is included.
+    ///
+    /// To get fully reproducible output, format a
+    /// [`ProfileSnapshot`](../struct.ProfileSnapshot.html) with known
+    /// durations, made with a
+    /// [`SnapshotBuilder`](../struct.SnapshotBuilder.html), using
+    /// [`get_formatted_snapshot`](../fn.get_formatted_snapshot.html).
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use stperf::format::ReportOptions;
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["update"], &[3_000_000])
+    ///     .add_scope(&["render"], &[1_000_000])
+    ///     .build();
+    /// assert_eq!(
+    ///     stperf::get_formatted_snapshot(&snapshot, &ReportOptions::deterministic()),
+    ///     concat!(
+    ///         "╶───╼ render  - 100.0%,   1 ms/loop, 1 samples\n",
+    ///         "╶───╼ update  - 100.0%,   3 ms/loop, 1 samples\n",
+    ///     )
+    /// );
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub fn deterministic() -> ReportOptions {
+        ReportOptions {
+            clock_warning: false,
+            sort_by_name: true,
+            ..Default::default()
+        }
+    }
+}
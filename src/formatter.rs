@@ -1,5 +1,32 @@
-use measurement::{self, Measurement};
-use format::{self, FormattingOptions};
+use format::{self, FormattingOptions, OpsPerSecond, Percentages, RankBy, ReportOptions};
+use measurement;
+use snapshot::{saturating_sum, ProfileSnapshot, ScopeNode};
+use std::cmp::{self, Reverse};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    /// The smallest non-zero difference between two `Instant`s
+    /// observed on this platform.
+    static ref CLOCK_RESOLUTION: Duration = probe_clock_resolution();
+    /// The aggregations added with `add_aggregation`.
+    static ref AGGREGATIONS: Mutex<Vec<(String, Aggregation)>> = Mutex::new(Vec::new());
+    /// The rankings of the previous outputs formatted with
+    /// `ReportOptions::rank_changes_only`, by the snapshots' thread
+    /// names, so each thread is compared to its own previous ranking.
+    static ref PREVIOUS_RANKINGS: Mutex<HashMap<Option<String>, Vec<String>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Shared, so the aggregations can be run without keeping
+/// `AGGREGATIONS` locked.
+type Aggregation = Arc<dyn Fn(&[u64]) -> f64 + Send + Sync>;
+
+/// Has the clock resolution warning already been printed?
+static CLOCK_WARNING_SHOWN: AtomicBool = AtomicBool::new(false);
 
 /// Prints out the data gathered by the profiler. Uses
 /// [`format::STREAMLINED`](format/static.STREAMLINED.html) as the
@@ -31,6 +58,42 @@ pub fn print_with_format(ops: FormattingOptions, decimals: usize) {
     println!("{}", get_formatted_string(ops, decimals));
 }
 
+/// Prints out the data gathered by the profiler, configured by the
+/// given [`ReportOptions`](format/struct.ReportOptions.html).
+pub fn print_with_options(options: &ReportOptions) {
+    println!("{}", get_formatted_string_with_options(options));
+}
+
+/// Adds a custom aggregation, which will be shown as an extra
+/// column in the formatted output, labeled with `name`. The
+/// aggregation is given every sample of a scope, in nanoseconds, and
+/// is run for every scope each time the output is formatted, so keep
+/// it reasonably light. Adding an aggregation with the name of
+/// an existing one replaces it.
+///
+/// ```
+/// stperf::add_aggregation("over 16ms", |samples| {
+///     samples.iter().filter(|&&sample| sample > 16_000_000).count() as f64
+/// });
+/// ```
+pub fn add_aggregation<T, F>(name: T, aggregation: F)
+where
+    T: Into<String>,
+    F: Fn(&[u64]) -> f64 + Send + Sync + 'static,
+{
+    let name = name.into();
+    let mut aggregations = AGGREGATIONS.lock().unwrap();
+    aggregations.retain(|(existing_name, _)| *existing_name != name);
+    aggregations.push((name, Arc::new(aggregation)));
+}
+
+/// Removes the custom aggregation called `name`, see
+/// [`add_aggregation`](fn.add_aggregation.html).
+pub fn remove_aggregation(name: &str) {
+    let mut aggregations = AGGREGATIONS.lock().unwrap();
+    aggregations.retain(|(existing_name, _)| existing_name != name);
+}
+
 /// Returns what [`print`](fn.print.html) prints, if you want to put it somewhere else
 /// than stdout.
 ///
@@ -43,119 +106,1012 @@ pub fn print_with_format(ops: FormattingOptions, decimals: usize) {
 ///    └───╼ rendering                -  33.3%, 100 ms/loop
 /// ```
 pub fn get_formatted_string(ops: FormattingOptions, decimals: usize) -> String {
+    get_formatted_string_with_options(&ReportOptions {
+        format: ops,
+        decimals,
+        ..Default::default()
+    })
+}
+
+/// Returns what [`print_with_options`](fn.print_with_options.html)
+/// prints.
+pub fn get_formatted_string_with_options(options: &ReportOptions) -> String {
+    if !options.per_thread {
+        return get_formatted_snapshot(&measurement::snapshot(), options);
+    }
+    let thread_options = ReportOptions {
+        trailing_newline: true,
+        ..options.clone()
+    };
     let mut result = String::new();
-    let children = measurement::get_measures();
-
-    let construct_tree_branch = |measurement: &Measurement| -> String {
-        let has_child;
-        let not_last_leaf;
-        if let Some(ref parent) = measurement.parent {
-            let parent = parent.get_mut();
-            has_child = measurement.has_children();
-            not_last_leaf = !parent.is_last_child_name(&measurement.name, true);
-        } else {
-            has_child = false;
-            not_last_leaf = false;
-        }
-
-        let mut branch = String::new();
-        for d in 0..measurement.depth {
-            if d == measurement.depth - 1 {
-                branch += if measurement.depth == 1 {
-                    ops.starting_branch
-                } else if not_last_leaf {
-                    ops.branching_branch
-                } else {
-                    ops.turning_branch
+    for snapshot in measurement::thread_snapshots() {
+        result += &get_formatted_snapshot(&snapshot, &thread_options);
+    }
+    if !options.trailing_newline && result.ends_with('\n') {
+        result.pop();
+    }
+    result
+}
+
+/// Returns the measurements taken so far as a nested RON structure,
+/// see
+/// [`ProfileSnapshot::to_ron`](struct.ProfileSnapshot.html#method.to_ron).
+pub fn get_ron() -> String {
+    measurement::snapshot().to_ron()
+}
+
+/// Returns the formatted string of the given
+/// [`ProfileSnapshot`](struct.ProfileSnapshot.html), like
+/// [`get_formatted_string_with_options`](fn.get_formatted_string_with_options.html)
+/// does for the current measurements.
+///
+/// Scopes with the same name are shown as separate rows if they have
+/// different parents, and as one row otherwise:
+///
+/// ```
+/// use stperf::format::ReportOptions;
+/// use stperf::SnapshotBuilder;
+///
+/// let snapshot = SnapshotBuilder::new()
+///     .add_scope(&["main"], &[10_000_000, 10_000_000])
+///     .add_scope(&["main", "update"], &[6_000_000, 6_000_000])
+///     .add_scope(&["main", "update", "physics"], &[2_000_000, 2_000_000])
+///     .add_scope(&["main", "update", "physics"], &[2_000_000, 2_000_000])
+///     .add_scope(&["main", "render"], &[3_000_000, 3_000_000])
+///     .add_scope(&["main", "render", "physics"], &[1_000_000, 1_000_000])
+///     .build();
+/// assert_eq!(
+///     stperf::get_formatted_snapshot(&snapshot, &ReportOptions::deterministic()),
+///     concat!(
+///         "╶──┬╼ main           - 100.0%,  10 ms/loop, 2 samples\n",
+///         "   ├──┬╼ render      -  30.0%,   3 ms/loop, 2 samples\n",
+///         "   │  └───╼ physics  -  33.3%,   1 ms/loop, 2 samples\n",
+///         "   └──┬╼ update      -  60.0%,   6 ms/loop, 2 samples\n",
+///         "      └───╼ physics  -  66.7%,   4 ms/loop, 4 samples\n",
+///     )
+/// );
+/// ```
+pub fn get_formatted_snapshot(snapshot: &ProfileSnapshot, options: &ReportOptions) -> String {
+    let mut result = String::new();
+    let mut root = Row {
+        name: String::from("root"),
+        duration: None,
+        samples: Vec::new(),
+        iterations: Vec::new(),
+        rss_growth: None,
+        absorbed: 0,
+        rank: None,
+        scope_paths: Vec::new(),
+        children: snapshot
+            .roots()
+            .iter()
+            .filter_map(|&id| snapshot.node(id))
+            .map(|node| Row::from_node(snapshot, node))
+            .collect(),
+    };
+
+    if let Some(thread_name) = snapshot.thread_name() {
+        result += &format!("thread: {}\n", thread_name);
+    }
+
+    if options.show_wall_clock {
+        if let Some(start) = snapshot.session_start() {
+            result += &format!(
+                "session: {} - {}\n",
+                format_utc(start),
+                format_utc(SystemTime::now())
+            );
+        }
+    }
+
+    if options.clock_warning {
+        if let Some(warning) = get_clock_warning(&root) {
+            result += &warning;
+        }
+    }
+
+    if options.worst_iteration {
+        if let Some((iteration, total)) = get_worst_iteration(&root) {
+            result += &format!(
+                "worst iteration: {} ({:.decimals$} ms)\n",
+                iteration,
+                total as f64 / 1_000_000.0,
+                decimals = options.decimals
+            );
+            root.keep_iteration(iteration);
+        }
+    }
+
+    if let Some(separator) = options.group_separator {
+        root.group_by_separator(separator);
+    }
+
+    if options.sort_by_name {
+        root.sort_by_name();
+    }
+
+    if let Some(min_percent) = options.absorb_below {
+        for child in &mut root.children {
+            child.absorb_below(min_percent, options);
+        }
+    }
+
+    if let Some(max_children) = options.max_children {
+        root.cap_children(max_children);
+    }
+
+    if options.rank_changes_only {
+        result += &get_rank_changes(&root, snapshot.thread_name(), options);
+        if !options.trailing_newline && result.ends_with('\n') {
+            result.pop();
+        }
+        return result;
+    }
+
+    if options.shared_root_percentages {
+        // Make the root's time the sum of the root-level rows, so
+        // they're percentages of it.
+        for child in &root.children {
+            if let Some(duration) = child.duration {
+                root.duration = Some(root.duration.unwrap_or(0).saturating_add(duration));
+            }
+            root.samples.extend(&child.samples);
+            root.iterations.extend(&child.iterations);
+        }
+    }
+    if let Some(count) = options.show_rank {
+        root.rank(count, options.rank_by);
+    }
+
+    let root_parent = if options.shared_root_percentages {
+        Some(&root)
+    } else {
+        None
+    };
+
+    let mut lines = Vec::new();
+    let children_count = root.children.len();
+    for (i, child) in root.children.iter().enumerate() {
+        construct_lines(
+            child,
+            root_parent,
+            child,
+            1,
+            i + 1 < children_count,
+            &mut Vec::new(),
+            options,
+            &mut lines,
+        );
+    }
+
+    let max_width = lines
+        .iter()
+        .map(|(branch, _)| branch.chars().count() + 1)
+        .max()
+        .unwrap_or(0);
+    for (branch, info_line) in lines {
+        result += &format!(
+            "{:max_width$} - {}\n",
+            branch,
+            info_line,
+            max_width = max_width
+        );
+    }
+
+    if options.show_coverage {
+        result += &format!("coverage: {:.1}%\n", 100.0 * snapshot.coverage());
+    }
+
+    for (path_a, path_b) in &options.ratios {
+        let path_a: Vec<&str> = path_a.iter().map(String::as_str).collect();
+        let path_b: Vec<&str> = path_b.iter().map(String::as_str).collect();
+        let ratio = match snapshot.ratio(&path_a, &path_b) {
+            Some(ratio) => format!("{:.2}", ratio),
+            None => String::from("no data"),
+        };
+        result += &format!("{} : {} = {}\n", path_a.join("/"), path_b.join("/"), ratio);
+    }
+
+    if !options.trailing_newline && result.ends_with('\n') {
+        result.pop();
+    }
+    result
+}
+
+/// A row in the formatted output, and the rows under it.
+struct Row {
+    name: String,
+    /// The total duration, with the overhead subtracted.
+    duration: Option<u64>,
+    /// The samples, in nanoseconds.
+    samples: Vec<u64>,
+    /// The root iterations the `samples` were recorded in.
+    iterations: Vec<usize>,
+    /// The largest growth of the resident set size during a sample.
+    rss_growth: Option<i64>,
+    /// The time of the children that were absorbed into this row,
+    /// see `ReportOptions::absorb_below`.
+    absorbed: u64,
+    /// The rank shown before the name, see `ReportOptions::show_rank`.
+    rank: Option<usize>,
+    /// The paths of the scopes the samples are from, for finding the
+    /// same scopes in `ReportOptions::baseline`.
+    scope_paths: Vec<Vec<String>>,
+    children: Vec<Row>,
+}
+
+impl Row {
+    fn from_node(snapshot: &ProfileSnapshot, node: &ScopeNode) -> Row {
+        Row {
+            name: node.name.clone(),
+            duration: node.duration,
+            samples: node.samples.clone(),
+            iterations: node.iterations.clone(),
+            rss_growth: node.rss_growth,
+            absorbed: 0,
+            rank: None,
+            scope_paths: vec![snapshot.path_of(node.id)],
+            children: snapshot
+                .children_of(node.id)
+                .into_iter()
+                .map(|child| Row::from_node(snapshot, child))
+                .collect(),
+        }
+    }
+
+    /// Moves the rows under this one whose names contain `separator`
+    /// under new rows named by the part before the separator,
+    /// recursively. The new rows' samples are the grouped rows'
+    /// samples combined.
+    fn group_by_separator(&mut self, separator: char) {
+        let mut children: Vec<Row> = Vec::new();
+        // Indices of the group rows in `children`, by name.
+        let mut groups: Vec<(String, usize)> = Vec::new();
+        for mut child in mem::take(&mut self.children) {
+            let (prefix, rest) = match child.name.find(separator) {
+                Some(i) => (
+                    child.name[..i].to_string(),
+                    child.name[i + separator.len_utf8()..].to_string(),
+                ),
+                None => {
+                    children.push(child);
+                    continue;
                 }
-            } else {
-                let width =
-                    ops.ending_branch.chars().count() - ops.continuing_branch.chars().count();
-                let mut branch_part = format!("{:width$}", "", width = width);
-
-                let generation = (measurement.depth - 1 - d) as u32;
-                if d > 0 && generation >= 1 {
-                    if let Some(ancestor) = measurement.get_ancestor(generation) {
-                        let ancestor = ancestor.get_mut();
-                        let younger_ancestor = measurement.get_ancestor(generation - 1).unwrap();
-                        let younger_ancestor = younger_ancestor.get_mut();
-                        if !ancestor.is_last_child_name(&younger_ancestor.name, false) {
-                            branch_part =
-                                format!("{:width$}", ops.continuing_branch, width = width);
-                        }
-                    }
+            };
+            child.name = rest;
+            let index = match groups.iter().find(|group| group.0 == prefix) {
+                Some(&(_, index)) => index,
+                None => {
+                    groups.push((prefix.clone(), children.len()));
+                    children.push(Row {
+                        name: prefix,
+                        duration: None,
+                        samples: Vec::new(),
+                        iterations: Vec::new(),
+                        rss_growth: None,
+                        absorbed: 0,
+                        rank: None,
+                        scope_paths: Vec::new(),
+                        children: Vec::new(),
+                    });
+                    children.len() - 1
                 }
-
-                branch += &branch_part
+            };
+            let group = &mut children[index];
+            if let Some(duration) = child.duration {
+                group.duration = Some(group.duration.unwrap_or(0).saturating_add(duration));
             }
+            group.samples.extend(&child.samples);
+            group.iterations.extend(&child.iterations);
+            group.scope_paths.extend(child.scope_paths.iter().cloned());
+            group.children.push(child);
+        }
+        self.children = children;
+        for child in &mut self.children {
+            child.group_by_separator(separator);
         }
+    }
 
-        branch += if has_child {
-            ops.turning_ending_branch
-        } else {
-            ops.ending_branch
+    /// Removes the samples that weren't measured in `iteration` from
+    /// this row and the rows under it, and then the rows left without
+    /// samples. The duration is scaled down with the samples, so the
+    /// overhead stays subtracted.
+    fn keep_iteration(&mut self, iteration: usize) {
+        let total = saturating_sum(self.samples.iter().cloned());
+        let (samples, iterations): (Vec<u64>, Vec<usize>) = self
+            .samples
+            .iter()
+            .zip(&self.iterations)
+            .filter(|&(_, &sample_iteration)| sample_iteration == iteration)
+            .unzip();
+        let kept = saturating_sum(samples.iter().cloned());
+        self.duration = match self.duration {
+            Some(_) if samples.is_empty() => None,
+            Some(_) if total == 0 => Some(0),
+            Some(duration) => Some((duration as f64 * kept as f64 / total as f64) as u64),
+            None => None,
         };
-        branch += " ";
-        branch += &measurement.name;
-        branch
-    };
+        self.samples = samples;
+        self.iterations = iterations;
+        for child in &mut self.children {
+            child.keep_iteration(iteration);
+        }
+        self.children
+            .retain(|child| !child.samples.is_empty() || !child.children.is_empty());
+    }
 
-    let mut max_width = 0;
-    for measurement in &children {
-        let width = construct_tree_branch(&measurement).chars().count() + 1;
-        if width > max_width {
-            max_width = width;
+    /// Sorts the rows under this one by their names, recursively.
+    fn sort_by_name(&mut self) {
+        self.children.sort_by(|a, b| a.name.cmp(&b.name));
+        for child in &mut self.children {
+            child.sort_by_name();
         }
     }
 
-    let mut index = 0;
-    let mut main_count = 1;
-    for measurement in children {
-        if index == 0 {
-            // Skip "root"
-            index = 1;
-            continue;
+    /// Keeps at most `max_children` of the slowest rows under this
+    /// one, and replaces the rest with a single row summing them up,
+    /// recursively. The kept rows stay in their order.
+    fn cap_children(&mut self, max_children: usize) {
+        for child in &mut self.children {
+            child.cap_children(max_children);
+        }
+        if self.children.len() <= max_children {
+            return;
+        }
+        let mut order: Vec<usize> = (0..self.children.len()).collect();
+        order.sort_by_key(|&i| Reverse(self.children[i].duration));
+        let mut kept = vec![false; self.children.len()];
+        for &i in order.iter().take(max_children) {
+            kept[i] = true;
         }
 
-        let branch = construct_tree_branch(&measurement);
-        let info_line;
-        if let Some(duration) = measurement.get_duration_ns() {
-            let count = measurement.durations.len();
+        let others_count = self.children.len() - max_children;
+        let mut others = Row {
+            name: format!("(… {} others)", others_count),
+            duration: None,
+            samples: Vec::new(),
+            iterations: Vec::new(),
+            rss_growth: None,
+            absorbed: 0,
+            rank: None,
+            scope_paths: Vec::new(),
+            children: Vec::new(),
+        };
+        let children = mem::take(&mut self.children);
+        for (child, kept) in children.into_iter().zip(kept) {
+            if kept {
+                self.children.push(child);
+                continue;
+            }
+            if let Some(duration) = child.duration {
+                others.duration = Some(others.duration.unwrap_or(0).saturating_add(duration));
+            }
+            others.samples.extend(&child.samples);
+            others.iterations.extend(&child.iterations);
+            others.scope_paths.extend(child.scope_paths.iter().cloned());
+            others.rss_growth = others.rss_growth.max(child.rss_growth);
+        }
+        self.children.push(others);
+    }
 
-            let parent_duration = if measurement.depth > 1 {
-                let parent = measurement.parent.unwrap();
-                let parent = parent.get_mut();
-                match parent.get_duration_ns() {
-                    Some(duration) => duration, // Parent has duration, use it
-                    None => duration,           // Parent has no duration, use own
-                }
+    /// Sets the ranks of the `count` slowest rows under this one, as
+    /// defined by `rank_by`.
+    fn rank(&mut self, count: usize, rank_by: RankBy) {
+        let mut times = Vec::new();
+        self.get_rank_times(rank_by, &mut times);
+        let mut order: Vec<usize> = (0..times.len()).filter(|&i| times[i].is_some()).collect();
+        order.sort_by_key(|&i| Reverse(times[i]));
+        let mut ranks = vec![None; times.len()];
+        for (rank, &i) in order.iter().take(count).enumerate() {
+            ranks[i] = Some(rank + 1);
+        }
+        self.set_ranks(&ranks, &mut 0);
+    }
+
+    /// Appends the times the rows under this one are ranked by to
+    /// `times`, parents before their children.
+    fn get_rank_times(&self, rank_by: RankBy, times: &mut Vec<Option<u64>>) {
+        for child in &self.children {
+            times.push(child.duration.map(|duration| match rank_by {
+                RankBy::SelfTime => duration.saturating_sub(saturating_sum(
+                    child.children.iter().filter_map(|row| row.duration),
+                )),
+                RankBy::Total => duration,
+            }));
+            child.get_rank_times(rank_by, times);
+        }
+    }
+
+    /// Returns the paths of the rows under this one, slowest first, as
+    /// defined by `rank_by`. The paths are the rows' names joined
+    /// with "/". Rows without samples are left out.
+    fn get_ranking(&self, rank_by: RankBy) -> Vec<String> {
+        let mut times = Vec::new();
+        self.get_rank_times(rank_by, &mut times);
+        let mut paths = Vec::new();
+        self.get_paths("", &mut paths);
+        let mut ranking: Vec<(String, u64)> = paths
+            .into_iter()
+            .zip(times)
+            .filter_map(|(path, time)| time.map(|time| (path, time)))
+            .collect();
+        ranking.sort_by_key(|&(_, time)| Reverse(time));
+        ranking.into_iter().map(|(path, _)| path).collect()
+    }
+
+    /// Appends the paths of the rows under this one to `paths`, in
+    /// the order of `get_rank_times`. `prefix` is the path of this
+    /// row.
+    fn get_paths(&self, prefix: &str, paths: &mut Vec<String>) {
+        for child in &self.children {
+            let path = if prefix.is_empty() {
+                child.name.clone()
             } else {
-                duration // No parent, use own
+                format!("{}/{}", prefix, child.name)
             };
+            paths.push(path.clone());
+            child.get_paths(&path, paths);
+        }
+    }
 
-            if measurement.depth == 1 {
-                main_count = count;
+    /// Sets the ranks of the rows under this one from `ranks`, in the
+    /// order of `get_rank_times`, starting from `index`.
+    fn set_ranks(&mut self, ranks: &[Option<usize>], index: &mut usize) {
+        for child in &mut self.children {
+            child.rank = ranks[*index];
+            *index += 1;
+            child.set_ranks(ranks, index);
+        }
+    }
+
+    /// Returns the total duration of the samples in each root
+    /// iteration, keyed by the iteration.
+    fn get_iteration_durations(&self) -> BTreeMap<usize, u64> {
+        let mut totals = BTreeMap::new();
+        for (sample, iteration) in self.samples.iter().zip(&self.iterations) {
+            let total = totals.entry(*iteration).or_insert(0);
+            *total = sample.saturating_add(*total);
+        }
+        totals
+    }
+
+    /// Removes the leaf rows under this one that take less than
+    /// `min_percent` of their parent's time, adding their time to
+    /// the parent's absorbed time. Leaves are removed from the
+    /// bottom up, so a row whose children were all absorbed can be
+    /// absorbed as well.
+    fn absorb_below(&mut self, min_percent: f64, options: &ReportOptions) {
+        for child in &mut self.children {
+            child.absorb_below(min_percent, options);
+        }
+        let percents: Vec<f64> = self
+            .children
+            .iter()
+            .map(|child| get_percent(child, Some(self), options))
+            .collect();
+        let children = mem::take(&mut self.children);
+        for (child, percent) in children.into_iter().zip(percents) {
+            if child.children.is_empty() && percent < min_percent {
+                self.absorbed = self.absorbed.saturating_add(child.duration.unwrap_or(0));
+            } else {
+                self.children.push(child);
             }
+        }
+    }
+}
 
-            info_line = format!(
-                "{:5.1}%, {:width$.decimals$} ms/loop, {} samples",
-                100.0 * (duration as f64 / parent_duration as f64),
-                (duration / main_count as u64) as f64 / 1_000_000.0,
-                count,
-                width = decimals + 3,
-                decimals = decimals
-            );
+/// Returns the root iteration with the most time spent in the
+/// root-level rows, and that time in nanoseconds.
+fn get_worst_iteration(root: &Row) -> Option<(usize, u64)> {
+    let mut totals: BTreeMap<usize, u64> = BTreeMap::new();
+    for child in &root.children {
+        for (iteration, duration) in child.get_iteration_durations() {
+            let total = totals.entry(iteration).or_insert(0);
+            *total = duration.saturating_add(*total);
+        }
+    }
+    totals.into_iter().max_by_key(|&(_, total)| total)
+}
+
+/// Constructs the tree branch and info line of `row`, and then
+/// recursively of its children, into `lines`.
+///
+/// `continuing` tells which of the ancestors' columns have a
+/// continuing branch, ie. which ancestors aren't the last child.
+#[allow(clippy::too_many_arguments)]
+fn construct_lines(
+    row: &Row,
+    parent: Option<&Row>,
+    root_row: &Row,
+    depth: usize,
+    not_last_leaf: bool,
+    continuing: &mut Vec<bool>,
+    options: &ReportOptions,
+    lines: &mut Vec<(String, String)>,
+) {
+    let ops = options.format;
+    let mut branch = String::new();
+    for (d, &continues) in continuing.iter().enumerate() {
+        let width = ops.ending_branch.chars().count() - ops.continuing_branch.chars().count();
+        if d > 0 && continues {
+            branch += &format!("{:width$}", ops.continuing_branch, width = width);
+        } else if let (true, Some(guide)) = (d > 0, options.indentation_guide) {
+            branch += &format!("{:width$}", guide, width = width);
         } else {
-            info_line = String::from("no data");
+            branch += &format!("{:width$}", "", width = width);
         }
+    }
+    branch += if depth == 1 {
+        ops.starting_branch
+    } else if not_last_leaf {
+        ops.branching_branch
+    } else {
+        ops.turning_branch
+    };
+    branch += if !row.children.is_empty() {
+        ops.turning_ending_branch
+    } else {
+        ops.ending_branch
+    };
+    branch += " ";
+    if let Some(rank) = row.rank {
+        branch += &format!("#{} ", rank);
+    }
+    branch += &row.name;
 
-        result += &format!(
-            "{:max_width$} - {}\n",
-            branch,
-            info_line,
-            max_width = max_width
+    let info_line = if let Some(duration) = row.duration {
+        let main_count = if root_row.duration.is_some() {
+            get_iteration_count(root_row)
+        } else {
+            1
+        };
+        let mut info_line = String::new();
+        if let Some(target) = options.budget_bar {
+            info_line += &get_budget_bar(duration / main_count as u64, target);
+            info_line += " ";
+        }
+        info_line += &format!(
+            "{:5.1}%, {:width$.decimals$} ms/loop, {} samples",
+            get_percent(row, parent, options),
+            (duration / main_count as u64) as f64 / 1_000_000.0,
+            row.samples.len(),
+            width = options.decimals + 3,
+            decimals = options.decimals
+        );
+        let show_ops_per_second = match options.ops_per_second {
+            OpsPerSecond::Off => false,
+            OpsPerSecond::All => true,
+            OpsPerSecond::Scopes(ref names) => names.contains(&row.name),
+        };
+        if show_ops_per_second && duration > 0 {
+            let rate = row.samples.len() as f64 / (duration as f64 / 1_000_000_000.0);
+            info_line += &format!(", {} ops/s", format_rate(rate));
+        }
+        if options.show_calls_per_iteration {
+            info_line += &get_calls_per_iteration(row, root_row);
+        }
+        if options.show_last_delta {
+            info_line += &get_last_delta(row, options);
+        }
+        if let Some(ref baseline) = options.baseline {
+            info_line += &get_baseline_delta(
+                duration / main_count as u64,
+                row,
+                root_row,
+                baseline,
+                options,
+            );
+        }
+        if let Some(rss_growth) = row.rss_growth {
+            info_line += &format!(", rss: {:+} KiB", rss_growth / 1024);
+        }
+        // Cloned out of the lock, so the aggregations can add or
+        // remove aggregations, or format the output themselves.
+        let aggregations = AGGREGATIONS.lock().unwrap().clone();
+        for (name, aggregation) in &aggregations {
+            info_line += &format!(
+                ", {}: {:.decimals$}",
+                name,
+                aggregation(&row.samples),
+                decimals = options.decimals
+            );
+        }
+        if row.absorbed > 0 {
+            info_line += &format!(
+                ", {:.decimals$} ms/loop absorbed",
+                (row.absorbed / main_count as u64) as f64 / 1_000_000.0,
+                decimals = options.decimals
+            );
+        }
+        info_line
+    } else {
+        match options.budget_bar {
+            Some(_) => format!("[{:1$}] no data", "", BUDGET_BAR_WIDTH),
+            None => String::from("no data"),
+        }
+    };
+    lines.push((branch, info_line));
+
+    continuing.push(not_last_leaf);
+    let children_count = row.children.len();
+    for (i, child) in row.children.iter().enumerate() {
+        construct_lines(
+            child,
+            Some(row),
+            root_row,
+            depth + 1,
+            i + 1 < children_count,
+            continuing,
+            options,
+            lines,
         );
-        index += 1;
     }
-    result
+    continuing.pop();
+}
+
+/// Returns how many root iterations `row` was measured in. A
+/// root-level row is usually measured once per iteration, but a
+/// group of them, or a root-level scope measured between frame marks
+/// several times, has more samples than iterations.
+fn get_iteration_count(row: &Row) -> usize {
+    row.iterations.iter().collect::<BTreeSet<_>>().len().max(1)
+}
+
+/// Returns the rows whose rank changed since the last time this was
+/// called for the same thread, one per line, and remembers the
+/// current ranking for the next time. See
+/// `ReportOptions::rank_changes_only`.
+fn get_rank_changes(root: &Row, thread_name: Option<&str>, options: &ReportOptions) -> String {
+    let ranking = root.get_ranking(options.rank_by);
+    let count = options.show_rank.unwrap_or(usize::MAX);
+    let thread_name = thread_name.map(str::to_string);
+    let mut previous_rankings = PREVIOUS_RANKINGS.lock().unwrap();
+    let previous = previous_rankings.remove(&thread_name).unwrap_or_default();
+    let previous_ranks: HashMap<&str, usize> = previous
+        .iter()
+        .enumerate()
+        .map(|(rank, path)| (path.as_str(), rank))
+        .collect();
+
+    let mut changes = String::new();
+    for (rank, path) in ranking.iter().enumerate() {
+        let previous_rank = previous_ranks.get(path.as_str()).cloned();
+        let was_ranked = match previous_rank {
+            Some(previous_rank) => previous_rank < count,
+            None => false,
+        };
+        if (rank >= count && !was_ranked) || previous_rank == Some(rank) {
+            continue;
+        }
+        match previous_rank {
+            Some(previous_rank) => {
+                changes += &format!("#{} {} (was #{})\n", rank + 1, path, previous_rank + 1)
+            }
+            None => changes += &format!("#{} {} (new)\n", rank + 1, path),
+        }
+    }
+    for (previous_rank, path) in previous.iter().enumerate().take(count) {
+        if !ranking.contains(path) {
+            changes += &format!("{} (was #{}, now gone)\n", path, previous_rank + 1);
+        }
+    }
+
+    previous_rankings.insert(thread_name, ranking);
+    changes
+}
+
+/// Returns the minimum, maximum and average amount of samples `row`
+/// has per root iteration, formatted for the info line. The
+/// iterations are the ones `root_row` was measured in.
+fn get_calls_per_iteration(row: &Row, root_row: &Row) -> String {
+    let mut calls: BTreeMap<usize, usize> = root_row
+        .iterations
+        .iter()
+        .map(|&iteration| (iteration, 0))
+        .collect();
+    for iteration in &row.iterations {
+        if let Some(count) = calls.get_mut(iteration) {
+            *count += 1;
+        }
+    }
+    let min = calls.values().cloned().min().unwrap_or(0);
+    let max = calls.values().cloned().max().unwrap_or(0);
+    let average = if calls.is_empty() {
+        0.0
+    } else {
+        calls.values().sum::<usize>() as f64 / calls.len() as f64
+    };
+    if min == max {
+        format!(", calls/iter: {}", min)
+    } else {
+        format!(", calls/iter: {}-{} (avg {:.1})", min, max, average)
+    }
+}
+
+/// Formats `rate` with a thousands separator if it's large, or with
+/// a decimal if it's small.
+fn format_rate(rate: f64) -> String {
+    if rate < 100.0 {
+        return format!("{:.1}", rate);
+    }
+    let digits = format!("{:.0}", rate);
+    let mut formatted = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+/// Returns the difference between the totals of `row` in the last
+/// two root iterations it was measured in, formatted for the info
+/// line, or an empty string if it wasn't measured in two. The totals
+/// are used instead of the last two samples, as the samples of
+/// grouped and merged rows aren't in the order they were measured.
+fn get_last_delta(row: &Row, options: &ReportOptions) -> String {
+    let totals = row.get_iteration_durations();
+    let mut totals = totals.values().rev();
+    let (previous, last) = match (totals.next(), totals.next()) {
+        (Some(&last), Some(&previous)) => (previous, last),
+        _ => return String::new(),
+    };
+    let delta = (last as f64 - previous as f64) / 1_000_000.0;
+    let delta = format!("{:+.decimals$} ms", delta, decimals = options.decimals);
+    let color = match last.cmp(&previous) {
+        _ if !options.ansi_colors => None,
+        cmp::Ordering::Greater => Some(ANSI_RED),
+        cmp::Ordering::Less => Some(ANSI_GREEN),
+        cmp::Ordering::Equal => None,
+    };
+    match color {
+        Some(color) => format!(", last delta: {}{}{}", color, delta, ANSI_RESET),
+        None => format!(", last delta: {}", delta),
+    }
+}
+
+/// Returns the difference between `per_loop`, the time per root
+/// iteration of `row`, and the time of the same scopes in
+/// `baseline`, formatted for the info line. `root_row` is the
+/// root-level row `row` is under.
+fn get_baseline_delta(
+    per_loop: u64,
+    row: &Row,
+    root_row: &Row,
+    baseline: &ProfileSnapshot,
+    options: &ReportOptions,
+) -> String {
+    let find = |path: &Vec<String>| {
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        baseline
+            .node_at_path(&path)
+            .filter(|node| node.duration.is_some())
+    };
+    let nodes: Vec<&ScopeNode> = row.scope_paths.iter().filter_map(find).collect();
+    if nodes.is_empty() {
+        return String::from(", vs baseline: new");
+    }
+    let baseline_duration = saturating_sum(nodes.iter().filter_map(|node| node.duration));
+    // Like the ms/loop, relative to the root iterations of the
+    // root-level scopes.
+    let iterations: BTreeSet<usize> = root_row
+        .scope_paths
+        .iter()
+        .filter_map(find)
+        .flat_map(|node| node.iterations.iter().cloned())
+        .collect();
+    let previous = baseline_duration / iterations.len().max(1) as u64;
+
+    let delta = (per_loop as f64 - previous as f64) / 1_000_000.0;
+    let mut delta = format!("{:+.decimals$} ms", delta, decimals = options.decimals);
+    if previous > 0 {
+        let percent = 100.0 * (per_loop as f64 - previous as f64) / previous as f64;
+        delta += &format!(" ({:+.1}%)", percent);
+    }
+    let color = match per_loop.cmp(&previous) {
+        _ if !options.ansi_colors => None,
+        cmp::Ordering::Greater => Some(ANSI_RED),
+        cmp::Ordering::Less => Some(ANSI_GREEN),
+        cmp::Ordering::Equal => None,
+    };
+    match color {
+        Some(color) => format!(", vs baseline: {}{}{}", color, delta, ANSI_RESET),
+        None => format!(", vs baseline: {}", delta),
+    }
+}
+
+const ANSI_RED: &str = "\x1b[31m";
+const ANSI_GREEN: &str = "\x1b[32m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// The width of the bars drawn for `ReportOptions::budget_bar`,
+/// excluding the brackets.
+const BUDGET_BAR_WIDTH: usize = 20;
+
+/// Returns a bar showing how much of `target` `duration` (in
+/// nanoseconds) takes. If `duration` is longer than `target`, the
+/// bar is full and ends in a "!" instead of a "]".
+fn get_budget_bar(duration: u64, target: Duration) -> String {
+    let target = measurement::to_nanos(target);
+    let fraction = if target == 0 {
+        1.0
+    } else {
+        duration as f64 / target as f64
+    };
+    let filled = ((fraction * BUDGET_BAR_WIDTH as f64).round() as usize).min(BUDGET_BAR_WIDTH);
+    let mut bar = String::from("[");
+    for i in 0..BUDGET_BAR_WIDTH {
+        bar.push(if i < filled { '#' } else { '.' });
+    }
+    bar.push(if fraction > 1.0 { '!' } else { ']' });
+    bar
+}
+
+/// Returns the percentage of `parent`'s time `row` took, calculated
+/// as defined by `options.percentages`. Root-level rows have no
+/// parent, unless `options.shared_root_percentages` is set.
+fn get_percent(row: &Row, parent: Option<&Row>, options: &ReportOptions) -> f64 {
+    let duration = match row.duration {
+        Some(duration) => duration,
+        None => return 0.0,
+    };
+    match options.percentages {
+        Percentages::Totals => {
+            let parent_duration = match parent.and_then(|parent| parent.duration) {
+                Some(duration) => duration, // Parent has duration, use it
+                None => duration,           // Parent has no duration, use own
+            };
+            if parent_duration == 0 {
+                // Everything was subtracted as overhead, avoid NaN.
+                return if duration == 0 { 100.0 } else { 0.0 };
+            }
+            100.0 * (duration as f64 / parent_duration as f64)
+        }
+        Percentages::PerIteration => match parent {
+            Some(parent) => get_average_iteration_percent(row, parent),
+            None => 100.0,
+        },
+    }
+}
+
+/// Calculates the percentage of `parent`'s time `row` took in each
+/// of the root iterations `parent` was measured in, and returns the
+/// average.
+fn get_average_iteration_percent(row: &Row, parent: &Row) -> f64 {
+    let durations = row.get_iteration_durations();
+    let parent_durations = parent.get_iteration_durations();
+    let mut percent_sum = 0.0;
+    let mut iterations = 0;
+    for (iteration, parent_duration) in parent_durations {
+        if parent_duration == 0 {
+            continue;
+        }
+        let duration = *durations.get(&iteration).unwrap_or(&0);
+        percent_sum += 100.0 * (duration as f64 / parent_duration as f64);
+        iterations += 1;
+    }
+    if iterations == 0 {
+        100.0
+    } else {
+        percent_sum / iterations as f64
+    }
+}
+
+/// Returns the shortest sample of `row` and the rows under it.
+fn get_shortest_sample(row: &Row) -> Option<u64> {
+    let shortest_child_sample = row.children.iter().filter_map(get_shortest_sample).min();
+    row.samples
+        .iter()
+        .cloned()
+        .chain(shortest_child_sample)
+        .min()
+}
+
+/// Clock resolutions finer than this aren't warned about, as they're
+/// fine for any sample long enough to be worth measuring.
+const COARSE_CLOCK_RESOLUTION: Duration = Duration::from_micros(1);
+
+/// Returns a warning about the clock's resolution, if it is coarse
+/// compared to the shortest sample, and the warning hasn't been
+/// shown yet.
+fn get_clock_warning(root: &Row) -> Option<String> {
+    if CLOCK_WARNING_SHOWN.load(Ordering::Relaxed) {
+        return None;
+    }
+    let shortest_sample = Duration::from_nanos(get_shortest_sample(root)?);
+    let resolution = *CLOCK_RESOLUTION;
+    if resolution < COARSE_CLOCK_RESOLUTION || shortest_sample >= resolution * 10 {
+        return None;
+    }
+    CLOCK_WARNING_SHOWN.store(true, Ordering::Relaxed);
+    Some(format!(
+        "warning: the clock's resolution is about {}, timings under {} are imprecise\n",
+        format_short_duration(resolution),
+        format_short_duration(resolution * 10)
+    ))
+}
+
+/// Formats `duration` in nanoseconds, microseconds or milliseconds,
+/// whichever fits it, eg. "15.6 µs".
+fn format_short_duration(duration: Duration) -> String {
+    let nanos = measurement::to_nanos(duration);
+    if nanos < 1_000 {
+        format!("{} ns", nanos)
+    } else if nanos < 1_000_000 {
+        format!("{:.1} µs", nanos as f64 / 1_000.0)
+    } else {
+        format!("{:.1} ms", nanos as f64 / 1_000_000.0)
+    }
+}
+
+/// Formats `time` as an ISO 8601 UTC timestamp with milliseconds,
+/// eg. "2024-03-01T12:00:05.250Z".
+fn format_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Converts the days since 1970-01-01 into a date in the proleptic
+    // Gregorian calendar, from Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153; // Starting from March
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Measures the clock's effective resolution by waiting for
+/// `Instant::now()` to change a few times.
+fn probe_clock_resolution() -> Duration {
+    let mut resolution = Duration::from_secs(1);
+    for _ in 0..5 {
+        let start = Instant::now();
+        let mut now = Instant::now();
+        while now == start {
+            now = Instant::now();
+        }
+        if now - start < resolution {
+            resolution = now - start;
+        }
+    }
+    resolution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(secs: u64, millis: u64) -> String {
+        format_utc(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn dates_are_formatted_in_utc() {
+        assert_eq!(utc(0, 0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(utc(946_684_799, 999), "1999-12-31T23:59:59.999Z");
+        assert_eq!(utc(946_684_800, 0), "2000-01-01T00:00:00.000Z");
+        assert_eq!(utc(1_709_208_005, 250), "2024-02-29T12:00:05.250Z");
+        assert_eq!(utc(4_107_542_400, 0), "2100-03-01T00:00:00.000Z");
+    }
 }
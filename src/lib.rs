@@ -108,14 +108,113 @@
 //! 1. Set an update interval, eg. 1 second.
 //! 2. Every interval, print out the data (eg. `stperf::print()`), and
 //!    cleanup (`stperf::reset()`).
+//!
 //! This way, you'll always have quite a few samples (1 second is a
 //! long amount of time to gather data), and they'll be fresh. And
 //! you'll avoid filling up your ram.
 //!
+//! ## Threads
+//! Every thread measures into its own tree, so measuring doesn't
+//! wait for other threads. When printing or taking a
+//! [`snapshot`](fn.snapshot.html), the trees are merged together,
+//! so scopes with the same path of names are shown as one row. To
+//! see each thread separately, use
+//! [`thread_snapshots`](fn.thread_snapshots.html), or set
+//! [`ReportOptions::per_thread`](format/struct.ReportOptions.html#structfield.per_thread).
+//!
+//! ```
+//! # #[macro_use] extern crate stperf; #[cfg(not(feature = "disabled"))] fn main() {
+//! use std::thread;
+//!
+//! let workers: Vec<_> = (0..4)
+//!     .map(|_| thread::spawn(|| {
+//!         perf_measure!("job");
+//!     }))
+//!     .collect();
+//! for worker in workers {
+//!     worker.join().unwrap();
+//! }
+//!
+//! let snapshot = stperf::snapshot();
+//! let job = snapshot.node(snapshot.roots()[0]).unwrap();
+//! assert_eq!(job.samples.len(), 4);
+//! # }
+//! # #[cfg(feature = "disabled")] fn main() {}
+//! ```
+//!
+//! The root iterations are still shared between the threads: by
+//! default, every root-level scope that finishes ends one, whichever
+//! thread it's in. If more than one thread measures, call
+//! [`frame_mark`](fn.frame_mark.html) in the thread that drives the
+//! frames, so the iterations (and the features based on them, like
+//! [`capture_next`](fn.capture_next.html)) follow its frames.
+//!
+//! The time spent waiting for the state shared between the threads
+//! isn't subtracted from the scopes, so a scope always takes at least
+//! as long as the scopes under it, however busy the other threads
+//! are.
+//!
+//! ```
+//! # #[macro_use] extern crate stperf; fn main() {
+//! use std::thread;
+//!
+//! let workers: Vec<_> = (0..4)
+//!     .map(|_| thread::spawn(|| {
+//!         for _ in 0..1000 {
+//!             perf_measure!("job");
+//!             {
+//!                 perf_measure!("inner");
+//!             }
+//!         }
+//!     }))
+//!     .collect();
+//! for worker in workers {
+//!     worker.join().unwrap();
+//! }
+//!
+//! for snapshot in stperf::thread_snapshots() {
+//!     let job = snapshot.node_at_path(&["job"]).unwrap();
+//!     let inner = snapshot.node_at_path(&["job", "inner"]).unwrap();
+//!     assert!(job.duration >= inner.duration);
+//! }
+//! # }
+//! ```
+//!
+//! ## `rss`-feature
+//! With the `rss` feature enabled, the process's resident set size
+//! is read at the start and end of every measurement, and the
+//! largest growth of each scope is shown in the output. This is
+//! only supported on Linux, and reading it adds quite a bit of
+//! overhead to every measurement. The reads are counted as overhead,
+//! so they don't make the scopes seem any faster or slower.
+//!
+//! ```
+//! # #[macro_use] extern crate stperf; #[cfg(not(feature = "disabled"))] fn main() {
+//! use std::time::{Duration, Instant};
+//!
+//! let spin = Duration::from_micros(200);
+//! {
+//!     perf_measure!("spin");
+//!     let start = Instant::now();
+//!     while start.elapsed() < spin {}
+//! }
+//! let snapshot = stperf::snapshot();
+//! let node = snapshot.node_at_path(&["spin"]).unwrap();
+//! assert!(node.duration.unwrap() >= 200_000);
+//! if cfg!(all(feature = "rss", target_os = "linux")) {
+//!     assert!(node.rss_growth.is_some());
+//! }
+//! # }
+//! # #[cfg(feature = "disabled")] fn main() {}
+//! ```
+//!
 //! ## `disabled`-feature
 //! Be sure to enable the `disabled` feature for your release builds,
 //! as this will practically make this crate disappear in place, even
-//! as your code stays the same.
+//! as your code stays the same. If you want to keep some
+//! measurements in release builds, but not all of them, use
+//! [`perf_measure_debug!`](macro.perf_measure_debug.html) for the
+//! ones that should only be measured in debug builds.
 //!
 //! # Examples
 //! ```
@@ -156,15 +255,33 @@ pub mod format;
 #[allow(dead_code, unused_variables)]
 mod measurement_tracker;
 pub use measurement_tracker::MeasurementTracker;
+mod scope_name;
+pub use scope_name::ScopeName;
+#[cfg_attr(feature = "disabled", allow(dead_code))]
+mod snapshot;
+pub use snapshot::{
+    OverheadPolicy, ProfileSnapshot, Regression, ScopeNode, SnapshotBuilder, Tolerance,
+};
 
 #[cfg(not(feature = "disabled"))]
 mod measurement;
 #[cfg(not(feature = "disabled"))]
-pub use measurement::{measure, reset};
+pub use measurement::{
+    calibrate_overhead, calibrated_overhead, capture_next, coverage, critical_path, current_path,
+    disable_scope, enable_scope, estimated_savings, frame_mark, measure, measure_scope,
+    overhead_policy, ratio, recent_frame_times, reset, reset_interval, set_overhead_policy,
+    snapshot, thread_snapshots, tree_hash, worst_frame_time, worst_sample,
+};
 #[cfg(not(feature = "disabled"))]
 mod formatter;
+#[cfg(all(feature = "rss", not(feature = "disabled")))]
+mod rss;
 #[cfg(not(feature = "disabled"))]
-pub use formatter::{get_formatted_string, print, print_with_format};
+pub use formatter::{
+    add_aggregation, get_formatted_snapshot, get_formatted_string,
+    get_formatted_string_with_options, get_ron, print, print_with_format, print_with_options,
+    remove_aggregation,
+};
 
 #[cfg(feature = "disabled")]
 #[allow(dead_code, unused_variables)]
@@ -190,3 +307,62 @@ macro_rules! perf_measure {
 macro_rules! perf_measure {
     ($s: expr) => {};
 }
+
+/// Logs the time between this call and the end of the current scope,
+/// for a [`ScopeName`](trait.ScopeName.html) instead of a string.
+#[cfg(not(feature = "disabled"))]
+#[macro_export]
+macro_rules! perf_measure_enum {
+    ($s: expr) => {
+        use std::time::Instant;
+        use stperf::measure_scope;
+        #[allow(unused_variables)]
+        let measurement = measure_scope(Instant::now(), &$s);
+    };
+}
+
+/// Logs the time between this call and the end of the current scope,
+/// for a [`ScopeName`](trait.ScopeName.html) instead of a string.
+#[cfg(feature = "disabled")]
+#[macro_export]
+macro_rules! perf_measure_enum {
+    ($s: expr) => {};
+}
+
+/// Like [`perf_measure!`](macro.perf_measure.html), except it only
+/// measures in debug builds (when `debug_assertions` are enabled),
+/// and expands to nothing otherwise. Useful for fine-grained
+/// measurements in hot code, which shouldn't cost anything in
+/// release builds, even without the `disabled`-feature.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// perf_measure_debug!("broadphase");
+/// if cfg!(debug_assertions) {
+///     assert_eq!(stperf::current_path(), vec!["broadphase"]);
+/// } else {
+///     assert!(stperf::current_path().is_empty());
+/// }
+/// # }
+/// ```
+#[cfg(not(feature = "disabled"))]
+#[macro_export]
+macro_rules! perf_measure_debug {
+    ($s: expr) => {
+        #[cfg(debug_assertions)]
+        use std::time::Instant;
+        #[cfg(debug_assertions)]
+        use stperf::measure;
+        #[cfg(debug_assertions)]
+        #[allow(unused_variables)]
+        let measurement = measure(Instant::now(), $s);
+    };
+}
+
+/// Like [`perf_measure!`](macro.perf_measure.html), except it only
+/// measures in debug builds.
+#[cfg(feature = "disabled")]
+#[macro_export]
+macro_rules! perf_measure_debug {
+    ($s: expr) => {};
+}
@@ -1,88 +1,1113 @@
 //! The backend for the measurements.
 
-use std::sync::{Arc, Mutex, MutexGuard, TryLockResult};
-use std::time::{Duration, Instant};
+use std::any::TypeId;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use measurement_tracker::MeasurementTracker;
+use scope_name::ScopeName;
+use snapshot::{saturating_sum, OverheadPolicy, ProfileSnapshot, ScopeNode};
 
 lazy_static! {
-    pub(crate) static ref MEASUREMENT_STACK: Mutex<Vec<MeasurementRef>> =
-        Mutex::new(vec![Measurement::new("root".to_string(), 0, None)]);
+    /// The measurement trees of every thread that has measured
+    /// something, in the order they started measuring.
+    static ref THREAD_TREES: Mutex<Vec<ThreadTree>> = Mutex::new(Vec::new());
+    /// The totals of the most recent root iterations, see
+    /// [`recent_frame_times`](fn.recent_frame_times.html).
+    static ref FRAME_TIMES: Mutex<FrameTimes> = Mutex::new(FrameTimes {
+        current: Duration::new(0, 0),
+        recent: VecDeque::with_capacity(FRAME_TIMES_CAPACITY),
+        worst: None,
+    });
+    /// The wall-clock time of the first measurement since the last
+    /// reset, see
+    /// [`ProfileSnapshot::session_start`](struct.ProfileSnapshot.html#method.session_start).
+    static ref SESSION_START: Mutex<Option<SystemTime>> = Mutex::new(None);
+    /// The names of the scopes that aren't measured, see
+    /// [`disable_scope`](fn.disable_scope.html).
+    static ref DISABLED_SCOPES: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    /// The ids of the scopes in [`snapshot`](fn.snapshot.html), by
+    /// their parent's id, scope id and name. Kept between snapshots,
+    /// so the ids don't depend on which thread measured the scope
+    /// first.
+    static ref MERGED_IDS: Mutex<HashMap<MergedPath, usize>> = Mutex::new(HashMap::new());
 }
 
+thread_local! {
+    static STACK: ThreadStack = ThreadStack::new();
+}
+
+/// The measurements currently going on in a thread. The first one is
+/// the root of the thread's tree, which is registered in
+/// `THREAD_TREES` when the stack is created.
+struct ThreadStack {
+    stack: RefCell<Vec<MeasurementRef>>,
+    /// How many unrecorded measurements are currently going on.
+    unrecorded_depth: Cell<usize>,
+    /// Shared with the `ThreadTree`, cleared when the thread exits.
+    alive: Arc<AtomicBool>,
+}
+
+impl ThreadStack {
+    fn new() -> ThreadStack {
+        let root = Measurement::new("root".to_string(), 0);
+        let alive = Arc::new(AtomicBool::new(true));
+        let current = thread::current();
+        let name = match current.name() {
+            Some(name) => name.to_string(),
+            None => format!("{:?}", current.id()),
+        };
+        THREAD_TREES.lock().unwrap().push(ThreadTree {
+            root: root.clone(),
+            alive: alive.clone(),
+            name,
+            exited_threads: false,
+        });
+        ThreadStack {
+            stack: RefCell::new(vec![root]),
+            unrecorded_depth: Cell::new(0),
+            alive,
+        }
+    }
+}
+
+impl Drop for ThreadStack {
+    fn drop(&mut self) {
+        self.alive.store(false, Ordering::Relaxed);
+        // Everything above the root should've ended before the thread
+        // exits, so the rest of the trackers were leaked. This is the
+        // only place leaked root-level trackers can be noticed, as
+        // until then, they can't be told apart from long measurements.
+        let stack = self.stack.get_mut();
+        if cfg!(debug_assertions) && stack.len() > 1 {
+            end_leaked_measurements(stack.split_off(1));
+        }
+    }
+}
+
+/// A thread's measurement tree. The tree is kept after the thread
+/// exits, until the next [`reset`](fn.reset.html).
+struct ThreadTree {
+    root: MeasurementRef,
+    /// Is the thread still running?
+    alive: Arc<AtomicBool>,
+    /// The thread's name, or its id if it has no name.
+    name: String,
+    /// Is this the tree the exited threads' trees are folded into by
+    /// [`reset_interval`](fn.reset_interval.html)?
+    exited_threads: bool,
+}
+
+/// Returns the roots of every thread's measurement tree.
+fn get_thread_roots() -> Vec<MeasurementRef> {
+    let trees = THREAD_TREES.lock().unwrap();
+    trees.iter().map(|tree| tree.root.clone()).collect()
+}
+
+/// How many root iteration totals are kept for
+/// [`recent_frame_times`](fn.recent_frame_times.html).
+const FRAME_TIMES_CAPACITY: usize = 256;
+
+struct FrameTimes {
+    /// The time spent in root-level scopes during the current root
+    /// iteration.
+    current: Duration,
+    recent: VecDeque<Duration>,
+    /// The slowest iteration's total since the last
+    /// [`reset`](fn.reset.html).
+    worst: Option<Duration>,
+}
+
+/// The index of the current root iteration, ie. how many times a
+/// root-level scope has finished, or how many times
+/// [`frame_mark`](fn.frame_mark.html) has been called.
+static ITERATION: AtomicUsize = AtomicUsize::new(0);
+/// The id for the next new measurement. The root is 0.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+/// Has [`frame_mark`](fn.frame_mark.html) been called? If so, only
+/// frame marks end root iterations.
+static FRAME_MARKS_USED: AtomicBool = AtomicBool::new(false);
+/// Are measurements being recorded? If not, `measure` does nothing.
+static RECORDING: AtomicBool = AtomicBool::new(true);
+/// How many root iterations are left to capture, if
+/// [`capture_next`](fn.capture_next.html) is capturing.
+static CAPTURE_REMAINING: AtomicUsize = AtomicUsize::new(0);
+/// Should the data be printed when the capture is done?
+static CAPTURE_PRINT: AtomicBool = AtomicBool::new(false);
+/// Was [`capture_next`](fn.capture_next.html) called in the middle
+/// of a root iteration, which has to end before the capture starts?
+static CAPTURE_SKIPPING: AtomicBool = AtomicBool::new(false);
+/// The overhead of a single measurement in nanoseconds, as measured
+/// by [`calibrate_overhead`](fn.calibrate_overhead.html). 0 if not
+/// calibrated.
+static CALIBRATED_OVERHEAD: AtomicUsize = AtomicUsize::new(0);
+/// Is the overhead policy
+/// [`OverheadPolicy::Own`](enum.OverheadPolicy.html#variant.Own)?
+static OWN_OVERHEAD_ONLY: AtomicBool = AtomicBool::new(false);
+/// Has `SESSION_START` been set? Avoids locking it for every
+/// measurement.
+static SESSION_STARTED: AtomicBool = AtomicBool::new(false);
+/// Are there any `DISABLED_SCOPES`? Avoids locking it for every
+/// measurement while there aren't.
+static SCOPES_DISABLED: AtomicBool = AtomicBool::new(false);
+
 /// Starts a measurement in the current scope. **Don't use this, use
 /// the [`perf_measure!`](macro.perf_measure.html) macro.**
 pub fn measure<T: Into<String>>(now: Instant, measurement_name: T) -> MeasurementTracker {
     let name = measurement_name.into();
-    let mut stack = MEASUREMENT_STACK.lock().unwrap();
-    let depth = stack.len();
+    let disabled = is_scope_disabled(&name);
+    start_measurement(now, disabled, move |parent| match parent.get_child(&name) {
+        Some(child) => Child::Existing(child),
+        None => Child::New(name, None),
+    })
+}
 
-    let parent = stack.get(depth - 1).unwrap().clone();
-    let measurement = Measurement::new(
-        name.clone(),
-        depth,
-        Some(MeasurementRef::from(parent.clone())),
-    );
+/// Starts a measurement of a [`ScopeName`](trait.ScopeName.html) in
+/// the current scope. **Don't use this, use the
+/// [`perf_measure_enum!`](macro.perf_measure_enum.html) macro.**
+pub fn measure_scope<S: ScopeName + 'static>(now: Instant, scope: &S) -> MeasurementTracker {
+    start_measurement(now, is_scope_disabled(scope.name()), |parent| {
+        let scope_id = (TypeId::of::<S>(), scope.id());
+        match parent.get_scope_child(scope_id, scope.name()) {
+            Some(child) => Child::Existing(child),
+            None => Child::New(scope.name().to_string(), Some(scope_id)),
+        }
+    })
+}
 
-    let mut parent = parent.get_mut();
-    if let Some(existing_measurement) = parent.get_child(&name) {
-        {
-            let mut measurement = existing_measurement.get_mut();
-            measurement.measuring_currently = true;
+/// The type and [`ScopeName::id`](trait.ScopeName.html#tymethod.id)
+/// of a scope measured with a `ScopeName`. The type is included so
+/// that the ids of different `ScopeName` types don't collide.
+type ScopeId = (TypeId, u32);
+
+/// A scope's parent's id in [`snapshot`](fn.snapshot.html), and its
+/// own scope id and name, for finding its id in `MERGED_IDS`.
+type MergedPath = (Option<usize>, Option<ScopeId>, String);
+
+/// The measurement `start_measurement` should push to the stack.
+enum Child {
+    /// A child that has been measured before.
+    Existing(MeasurementRef),
+    /// A new child with the given name and scope id.
+    New(String, Option<ScopeId>),
+}
+
+/// Pushes the child of the current measurement returned by
+/// `find_child` to the stack, and returns the tracker for it. If the
+/// scope is `disabled`, nothing is pushed.
+fn start_measurement<F>(now: Instant, disabled: bool, find_child: F) -> MeasurementTracker
+where
+    F: FnOnce(&mut Measurement) -> Child,
+{
+    STACK.with(|local| {
+        if disabled || !RECORDING.load(Ordering::Relaxed) {
+            // Root-level scopes are still timed for the recent frame
+            // times, unless they're disabled.
+            let outermost = local.unrecorded_depth.get() == 0;
+            local.unrecorded_depth.set(local.unrecorded_depth.get() + 1);
+            let root_level = !disabled && outermost && local.stack.borrow().len() == 1;
+            return MeasurementTracker {
+                start_time: now,
+                overhead: Duration::new(0, 0),
+                recording: false,
+                root_level,
+                start_rss: None,
+                depth: 0,
+                not_send: PhantomData,
+            };
         }
-        stack.push(existing_measurement.clone());
-    } else {
-        stack.push(measurement.clone());
-        parent.children.push(measurement);
-        parent.children_names.push(name);
+
+        if !SESSION_STARTED.swap(true, Ordering::Relaxed) {
+            *SESSION_START.lock().unwrap() = Some(SystemTime::now());
+        }
+
+        let mut stack = local.stack.borrow_mut();
+        push_child(&mut stack, now, find_child)
+    })
+}
+
+/// Does the actual pushing for `start_measurement`.
+fn push_child<F>(stack: &mut Vec<MeasurementRef>, now: Instant, find_child: F) -> MeasurementTracker
+where
+    F: FnOnce(&mut Measurement) -> Child,
+{
+    let depth = stack.len();
+
+    let parent_ref = stack.get(depth - 1).unwrap().clone();
+    let mut parent = parent_ref.get_mut();
+    let activation = parent.activations;
+    let first_in_activation = match find_child(&mut parent) {
+        Child::Existing(existing_measurement) => {
+            let first_in_activation = {
+                let mut measurement = existing_measurement.get_mut();
+                measurement.start_activation(activation)
+            };
+            stack.push(existing_measurement);
+            first_in_activation
+        }
+        Child::New(name, scope_id) => {
+            let measurement = Measurement::new(name, depth);
+            measurement.get_mut().start_activation(activation);
+            stack.push(measurement.clone());
+            parent.children.push(measurement);
+            parent.children_scope_ids.push(scope_id);
+            true
+        }
+    };
+    if first_in_activation {
+        parent.active_children += 1;
+        parent.max_child_count = parent.max_child_count.max(parent.active_children);
     }
 
+    // Read before the overhead is taken, so the read is a part of it.
+    let start_rss = get_resident_set_size();
     MeasurementTracker {
         start_time: now,
         overhead: Instant::now() - now,
+        recording: true,
+        root_level: depth == 1,
+        start_rss,
+        depth,
+        not_send: PhantomData,
     }
 }
 
 impl Drop for MeasurementTracker {
     fn drop(&mut self) {
+        if !self.recording {
+            STACK.with(|local| local.unrecorded_depth.set(local.unrecorded_depth.get() - 1));
+            if self.root_level {
+                FRAME_TIMES.lock().unwrap().current += Instant::now() - self.start_time;
+                if !FRAME_MARKS_USED.load(Ordering::Relaxed) {
+                    end_iteration();
+                }
+            }
+            return;
+        }
+
         let latter_overhead_start = Instant::now();
-        let mut stack = MEASUREMENT_STACK.lock().unwrap();
-        let latest_measurement = stack.pop().unwrap();
-        let mut measurement = latest_measurement.get_mut();
-        measurement.measuring_currently = false;
-        measurement.overhead += self.overhead;
-        measurement.durations.push(Instant::now() - self.start_time);
-        measurement.overhead += Instant::now() - latter_overhead_start;
+        // Read before the duration is taken, so the read is a part of
+        // both the sample and the overhead subtracted from it.
+        let end_rss = self.start_rss.and_then(|_| get_resident_set_size());
+        let duration;
+        let iteration_ended;
+        {
+            let popped =
+                STACK.with(|local| pop_measurement(&mut local.stack.borrow_mut(), self.depth));
+            let latest_measurement = match popped {
+                Some(measurement) => measurement,
+                None => return,
+            };
+            let mut measurement = latest_measurement.get_mut();
+            measurement.measuring_currently = false;
+            measurement.overhead += self.overhead;
+            let end = Instant::now();
+            duration = end - self.start_time;
+            // The overhead ends with the sample, so it's always a part
+            // of it. The bookkeeping below is left out of both, so
+            // waiting for the shared state doesn't make a scope seem
+            // shorter than the scopes under it.
+            measurement.overhead += end - latter_overhead_start;
+            measurement.durations.push(duration);
+            measurement.lifetime_max = measurement.lifetime_max.max(Some(duration));
+            measurement
+                .iterations
+                .push(ITERATION.load(Ordering::Relaxed));
+            if let (Some(start_rss), Some(end_rss)) = (self.start_rss, end_rss) {
+                let delta = end_rss as i64 - start_rss as i64;
+                let largest_growth = match measurement.rss_growth {
+                    Some(growth) => delta > growth,
+                    None => true,
+                };
+                if largest_growth {
+                    measurement.rss_growth = Some(delta);
+                }
+            }
+            iteration_ended = measurement.depth == 1 && !FRAME_MARKS_USED.load(Ordering::Relaxed);
+        }
+        if self.root_level {
+            FRAME_TIMES.lock().unwrap().current += duration;
+        }
+        if iteration_ended {
+            end_iteration();
+        }
+    }
+}
+
+/// Pops the measurement at `depth` off the stack. In debug builds,
+/// the measurements left above it are popped as well, as their
+/// trackers must've been leaked (eg. with `mem::forget`), and a
+/// warning is printed. Otherwise, every measurement after them would
+/// end up under them. Returns `None` if the measurement isn't on the
+/// stack anymore.
+fn pop_measurement(stack: &mut Vec<MeasurementRef>, depth: usize) -> Option<MeasurementRef> {
+    if cfg!(debug_assertions) {
+        if stack.len() <= depth {
+            eprintln!("stperf: a measurement ended after the measurements around it, ignoring it");
+            return None;
+        }
+        if stack.len() > depth + 1 {
+            end_leaked_measurements(stack.split_off(depth + 1));
+        }
     }
+    stack.pop()
 }
 
-/// Resets the measurement data.
+/// Marks the measurements of leaked trackers as ended, and warns
+/// about them.
+fn end_leaked_measurements(leaked: Vec<MeasurementRef>) {
+    let names: Vec<String> = leaked
+        .iter()
+        .map(|leaked| {
+            let mut leaked = leaked.get_mut();
+            leaked.measuring_currently = false;
+            leaked.name.clone()
+        })
+        .collect();
+    eprintln!(
+        "stperf: the measurements {:?} were never ended, was a MeasurementTracker leaked?",
+        names
+    );
+}
+
+/// Returns `duration` in nanoseconds, or `u64::MAX` if it doesn't
+/// fit.
+pub(crate) fn to_nanos(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .saturating_mul(1_000_000_000)
+        .saturating_add(duration.subsec_nanos() as u64)
+}
+
+#[cfg(feature = "rss")]
+fn get_resident_set_size() -> Option<u64> {
+    ::rss::resident_set_size()
+}
+
+#[cfg(not(feature = "rss"))]
+fn get_resident_set_size() -> Option<u64> {
+    None
+}
+
+/// Moves on to the next root iteration, and stops the capture if it
+/// has captured enough iterations.
+fn end_iteration() {
+    ITERATION.fetch_add(1, Ordering::Relaxed);
+    {
+        let mut frame_times = FRAME_TIMES.lock().unwrap();
+        if frame_times.recent.len() == FRAME_TIMES_CAPACITY {
+            frame_times.recent.pop_front();
+        }
+        let current = frame_times.current;
+        frame_times.recent.push_back(current);
+        frame_times.worst = frame_times.worst.max(Some(current));
+        frame_times.current = Duration::new(0, 0);
+    }
+    if CAPTURE_SKIPPING.swap(false, Ordering::Relaxed) {
+        // Only part of the iteration was measured, so it's thrown
+        // away, and the capture starts from the next one.
+        clear_measurements(false);
+        FRAME_TIMES.lock().unwrap().worst = None;
+        return;
+    }
+    // Decremented atomically, so when several threads end iterations,
+    // exactly one of them ends the capture.
+    let remaining =
+        CAPTURE_REMAINING.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+            remaining.checked_sub(1)
+        });
+    if let Ok(remaining) = remaining {
+        if remaining == 1 {
+            RECORDING.store(false, Ordering::Relaxed);
+            if CAPTURE_PRINT.load(Ordering::Relaxed) {
+                ::formatter::print();
+            }
+        }
+    }
+}
+
+/// Is the scope named `name` disabled by
+/// [`disable_scope`](fn.disable_scope.html)?
+fn is_scope_disabled(name: &str) -> bool {
+    SCOPES_DISABLED.load(Ordering::Relaxed) && DISABLED_SCOPES.lock().unwrap().contains(name)
+}
+
+/// Stops measuring the scopes named `name`, until
+/// [`enable_scope`](fn.enable_scope.html) is called for it. The
+/// scopes under a disabled scope are still measured, and show up
+/// under the disabled scope's parent instead. Useful for leaving out
+/// a single noisy scope, eg. one around logging, that adds too much
+/// overhead. The data already measured for the scope is kept until
+/// the next [`reset`](fn.reset.html).
+///
+/// The check is done for every measurement: while no scope is
+/// disabled, it's a single atomic load, but after that, it locks a
+/// mutex and looks the name up in a `HashSet`, which makes every
+/// measurement a bit slower.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// stperf::disable_scope("logging");
+/// {
+///     perf_measure!("logging");
+///     assert!(stperf::current_path().is_empty());
+/// }
+/// stperf::enable_scope("logging");
+/// {
+///     perf_measure!("logging");
+///     assert_eq!(stperf::current_path(), vec!["logging"]);
+/// }
+/// # }
+/// ```
+pub fn disable_scope(name: &str) {
+    let mut disabled_scopes = DISABLED_SCOPES.lock().unwrap();
+    disabled_scopes.insert(name.to_string());
+    SCOPES_DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Starts measuring the scopes named `name` again, after
+/// [`disable_scope`](fn.disable_scope.html).
+pub fn enable_scope(name: &str) {
+    let mut disabled_scopes = DISABLED_SCOPES.lock().unwrap();
+    disabled_scopes.remove(name);
+    SCOPES_DISABLED.store(!disabled_scopes.is_empty(), Ordering::Relaxed);
+}
+
+/// Sets which overheads are subtracted from the scopes' durations,
+/// for the following snapshots and printouts. The default is
+/// [`OverheadPolicy::Inclusive`](enum.OverheadPolicy.html#variant.Inclusive),
+/// which keeps every scope's self time free of measuring overhead.
+/// With [`OverheadPolicy::Own`](enum.OverheadPolicy.html#variant.Own),
+/// the overhead of the scopes under a scope shows up as its self
+/// time instead.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// use stperf::OverheadPolicy;
+/// # use std::thread; use std::time::Duration;
+///
+/// for _ in 0..100 {
+///     perf_measure!("main");
+///     {
+///         perf_measure!("physics");
+///         thread::sleep(Duration::from_micros(10));
+///     }
+///     {
+///         perf_measure!("render");
+///     }
+/// }
+///
+/// // The parent's duration covers its children under both policies.
+/// assert_eq!(stperf::overhead_policy(), OverheadPolicy::Inclusive);
+/// for &policy in &[OverheadPolicy::Inclusive, OverheadPolicy::Own] {
+///     stperf::set_overhead_policy(policy);
+///     let snapshot = stperf::snapshot();
+///     let duration = |path: &[&str]| snapshot.node_at_path(path).unwrap().duration.unwrap();
+///     let children = duration(&["main", "physics"]) + duration(&["main", "render"]);
+///     assert!(duration(&["main"]) >= children);
+/// }
+/// stperf::set_overhead_policy(OverheadPolicy::Inclusive);
+/// # }
+/// ```
+pub fn set_overhead_policy(policy: OverheadPolicy) {
+    OWN_OVERHEAD_ONLY.store(policy == OverheadPolicy::Own, Ordering::Relaxed);
+}
+
+/// Returns the policy set by
+/// [`set_overhead_policy`](fn.set_overhead_policy.html).
+pub fn overhead_policy() -> OverheadPolicy {
+    if OWN_OVERHEAD_ONLY.load(Ordering::Relaxed) {
+        OverheadPolicy::Own
+    } else {
+        OverheadPolicy::Inclusive
+    }
+}
+
+/// Marks the end of a frame. By default, every time a root-level
+/// scope finishes is considered the end of a root iteration (eg. a
+/// frame). After the first call to this function, only frame marks
+/// end iterations, so the samples will be segmented by the marked
+/// frames, regardless of how many root-level scopes a frame has.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// for _ in 0..10 {
+///     {
+///         perf_measure!("update");
+///     }
+///     {
+///         perf_measure!("render");
+///     }
+///     stperf::frame_mark();
+/// }
+/// # }
+/// ```
+pub fn frame_mark() {
+    FRAME_MARKS_USED.store(true, Ordering::Relaxed);
+    end_iteration();
+}
+
+/// Estimates the overhead of a single measurement by running a lot
+/// of empty ones, and returns it. After this, the calibrated overhead
+/// is subtracted once for every sample, instead of the overhead
+/// captured during each measurement, which misses some of the cost
+/// of the [`perf_measure!`](macro.perf_measure.html) macro. This
+/// makes the timings of small scopes more accurate, especially in
+/// debug builds.
+///
+/// The calibration takes a few milliseconds, so it's best done once
+/// at startup. The empty measurements don't show up in the data.
+///
+/// ```
+/// let overhead = stperf::calibrate_overhead();
+/// assert_eq!(stperf::calibrated_overhead(), Some(overhead));
+/// ```
+pub fn calibrate_overhead() -> Duration {
+    const ROUNDS: u32 = 10_000;
+    {
+        // Measure under a temporary parent that isn't a part of the
+        // tree, so the measurements can be thrown away afterwards.
+        STACK.with(|local| {
+            let mut stack = local.stack.borrow_mut();
+            let calibration = Measurement::new("calibration".to_string(), stack.len());
+            stack.push(calibration);
+        });
+    }
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let _tracker = measure(Instant::now(), "overhead");
+    }
+    let overhead = (Instant::now() - start) / ROUNDS;
+    STACK.with(|local| local.stack.borrow_mut().pop());
+
+    let overhead_ns = to_nanos(overhead);
+    CALIBRATED_OVERHEAD.store(overhead_ns.max(1) as usize, Ordering::Relaxed);
+    Duration::from_nanos(overhead_ns.max(1))
+}
+
+/// Returns the overhead measured by
+/// [`calibrate_overhead`](fn.calibrate_overhead.html), or `None` if
+/// it hasn't been called.
+pub fn calibrated_overhead() -> Option<Duration> {
+    match CALIBRATED_OVERHEAD.load(Ordering::Relaxed) {
+        0 => None,
+        overhead => Some(Duration::from_nanos(overhead as u64)),
+    }
+}
+
+/// Returns the total of the slowest root iteration (see
+/// [`recent_frame_times`](fn.recent_frame_times.html)) since the last
+/// [`reset`](fn.reset.html), or `None` if no iteration has ended
+/// yet. The total is kept over
+/// [`reset_interval`](fn.reset_interval.html).
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// {
+///     perf_measure!("main");
+/// }
+/// assert!(stperf::worst_frame_time().is_some());
+/// stperf::reset();
+/// assert!(stperf::worst_frame_time().is_none());
+/// # }
+/// ```
+pub fn worst_frame_time() -> Option<Duration> {
+    FRAME_TIMES.lock().unwrap().worst
+}
+
+/// Returns the totals of the last `count` root iterations (see
+/// [`frame_mark`](fn.frame_mark.html)), oldest first. The total of
+/// an iteration is the time spent in root-level scopes during it.
+///
+/// Up to 256 of the most recent totals are kept, regardless of
+/// [`reset`](fn.reset.html), and they're recorded even when
+/// [`capture_next`](fn.capture_next.html) has stopped the other
+/// measurements, so this works for eg. a frame time graph that's
+/// always visible.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// for _ in 0..5 {
+///     perf_measure!("main");
+/// }
+/// assert_eq!(stperf::recent_frame_times(3).len(), 3);
+/// assert_eq!(stperf::recent_frame_times(10).len(), 5);
+/// # }
+/// ```
+pub fn recent_frame_times(count: usize) -> Vec<Duration> {
+    let frame_times = FRAME_TIMES.lock().unwrap();
+    let skipped = frame_times.recent.len().saturating_sub(count);
+    frame_times.recent.iter().skip(skipped).cloned().collect()
+}
+
+/// Resets the measurement data, and captures only the next
+/// `iterations` root iterations (see
+/// [`frame_mark`](fn.frame_mark.html)). After that, measuring stops
+/// and [`perf_measure!`](macro.perf_measure.html) does nothing,
+/// until [`reset`](fn.reset.html) is called. If `print_when_done`
+/// is true, the data is printed with [`print`](fn.print.html) when
+/// the capture is done.
+///
+/// Useful for capturing a specific moment, eg. when a debug key is
+/// pressed.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// stperf::capture_next(2, false);
+/// for _ in 0..5 {
+///     perf_measure!("main");
+/// }
+/// assert_eq!(stperf::snapshot().nodes()[0].samples.len(), 2);
+/// # }
+/// ```
+///
+/// If this is called in the middle of a root iteration, eg. inside a
+/// root-level scope or after [`frame_mark`](fn.frame_mark.html) has
+/// been used, the rest of the current iteration isn't captured, and
+/// the capture starts from the next one:
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// for frame in 0..5 {
+///     perf_measure!("frame");
+///     {
+///         perf_measure!("update");
+///     }
+///     if frame == 1 {
+///         stperf::capture_next(2, false);
+///     }
+/// }
+/// let snapshot = stperf::snapshot();
+/// assert_eq!(snapshot.node_at_path(&["frame"]).unwrap().iterations, vec![2, 3]);
+/// assert_eq!(snapshot.node_at_path(&["frame", "update"]).unwrap().iterations, vec![2, 3]);
+/// # }
+/// ```
+pub fn capture_next(iterations: usize, print_when_done: bool) {
+    reset();
+    let mid_iteration =
+        STACK.with(|local| local.stack.borrow().len() > 1 || local.unrecorded_depth.get() > 0);
+    CAPTURE_REMAINING.store(iterations, Ordering::Relaxed);
+    CAPTURE_PRINT.store(print_when_done, Ordering::Relaxed);
+    CAPTURE_SKIPPING.store(
+        iterations > 0 && (mid_iteration || FRAME_MARKS_USED.load(Ordering::Relaxed)),
+        Ordering::Relaxed,
+    );
+    RECORDING.store(iterations > 0, Ordering::Relaxed);
+}
+
+/// Resets the measurement data. If measuring was stopped by
+/// [`capture_next`](fn.capture_next.html), it is started again.
 ///
 /// **Warning**: This will wipe all measurements from the memory!
 pub fn reset() {
-    let stack = MEASUREMENT_STACK.lock().unwrap();
-    let root = stack.get(0).unwrap().get_mut();
-    let children = root.collect_all_children_arc();
-
-    for i in 0..children.len() {
-        let mut child = children[i].get_mut();
-        if !child.measuring_currently {
-            child.remove_while_locked();
-        } else {
-            child.clear_durations();
-        }
+    reset_measurements(false);
+    FRAME_TIMES.lock().unwrap().worst = None;
+}
+
+/// Like [`reset`](fn.reset.html), except the longest sample of each
+/// scope ([`ScopeNode::lifetime_max`](struct.ScopeNode.html#structfield.lifetime_max)),
+/// the [`worst_sample`](fn.worst_sample.html) and the
+/// [`worst_frame_time`](fn.worst_frame_time.html) are kept. Useful
+/// for resetting every interval to keep the averages fresh, while
+/// still keeping track of the worst cases of the whole run.
+///
+/// The scopes are kept as well, so the ones which aren't measured
+/// again show up without data. The trees of the threads that have
+/// exited are folded into one, shown as "exited threads" in
+/// [`thread_snapshots`](fn.thread_snapshots.html), so short-lived
+/// threads don't pile up between the intervals.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// {
+///     perf_measure!("main");
+/// }
+/// stperf::reset_interval();
+/// let snapshot = stperf::snapshot();
+/// let main = snapshot.node(snapshot.roots()[0]).unwrap();
+/// assert!(main.samples.is_empty());
+/// assert!(main.lifetime_max.is_some());
+/// assert!(stperf::worst_sample().is_some());
+///
+/// for _ in 0..10 {
+///     std::thread::spawn(|| {
+///         perf_measure!("job");
+///     })
+///     .join()
+///     .unwrap();
+/// }
+/// stperf::reset_interval();
+/// let snapshots = stperf::thread_snapshots();
+/// assert_eq!(snapshots.len(), 2);
+/// assert_eq!(snapshots[1].thread_name(), Some("exited threads"));
+/// let job = snapshots[1].node_at_path(&["job"]).unwrap();
+/// assert!(job.lifetime_max.is_some());
+/// # }
+/// ```
+pub fn reset_interval() {
+    reset_measurements(true);
+}
+
+/// Does the resetting for `reset` and `reset_interval`.
+fn reset_measurements(keep_lifetime: bool) {
+    CAPTURE_REMAINING.store(0, Ordering::Relaxed);
+    CAPTURE_SKIPPING.store(false, Ordering::Relaxed);
+    RECORDING.store(true, Ordering::Relaxed);
+    clear_measurements(keep_lifetime);
+}
+
+/// Clears the measurement data, without touching the capture.
+fn clear_measurements(keep_lifetime: bool) {
+    SESSION_STARTED.store(false, Ordering::Relaxed);
+    *SESSION_START.lock().unwrap() = None;
+    for root in get_thread_roots() {
+        root.get_mut().reset_children(keep_lifetime);
+    }
+    let mut trees = THREAD_TREES.lock().unwrap();
+    if keep_lifetime {
+        fold_exited_trees(&mut trees);
+        return;
+    }
+    MERGED_IDS.lock().unwrap().clear();
+    // The exited threads' trees are empty now, as nothing can be
+    // measured in them anymore.
+    trees.retain(|tree| tree.alive.load(Ordering::Relaxed));
+}
+
+/// Folds the trees of the exited threads into a single tree, which
+/// keeps their scopes and the longest samples of them. Otherwise
+/// `reset_interval` would keep every exited thread's tree around.
+fn fold_exited_trees(trees: &mut Vec<ThreadTree>) {
+    let (exited, kept): (Vec<ThreadTree>, Vec<ThreadTree>) = trees
+        .drain(..)
+        .partition(|tree| !tree.exited_threads && !tree.alive.load(Ordering::Relaxed));
+    *trees = kept;
+    if exited.is_empty() {
+        return;
+    }
+    if !trees.iter().any(|tree| tree.exited_threads) {
+        trees.push(ThreadTree {
+            root: Measurement::new("root".to_string(), 0),
+            alive: Arc::new(AtomicBool::new(false)),
+            name: "exited threads".to_string(),
+            exited_threads: true,
+        });
     }
+    let folded = trees.iter().find(|tree| tree.exited_threads).unwrap();
+    let mut folded = folded.root.get_mut();
+    for tree in exited {
+        folded.fold_lifetimes(&tree.root.get_mut());
+    }
+}
+
+/// Returns the names of the scopes that are currently being
+/// measured, from the shallowest to the innermost one. Useful for
+/// showing where the execution is right now, eg. in a debug overlay
+/// or when diagnosing a hang.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// perf_measure!("main");
+/// {
+///     perf_measure!("update");
+///     assert_eq!(stperf::current_path(), vec!["main", "update"]);
+/// }
+/// assert_eq!(stperf::current_path(), vec!["main"]);
+/// # }
+/// ```
+pub fn current_path() -> Vec<String> {
+    STACK.with(|local| {
+        local
+            .stack
+            .borrow()
+            .iter()
+            .skip(1) // Skip "root"
+            .map(|measurement| measurement.get_mut().name.clone())
+            .collect()
+    })
 }
 
-/// Returns a `Vec` of all the
-/// [`Measurement`](struct.Measurement.html)s taken so far.
+/// Returns a [`ProfileSnapshot`](struct.ProfileSnapshot.html) of the
+/// measurements taken so far. The measurements of every thread are
+/// merged together, so scopes with the same path are shown as one,
+/// no matter which thread they were measured in. Like in a single
+/// thread, different [`ScopeName`](trait.ScopeName.html) types with
+/// the same name are kept apart. The merged
+/// scopes get ids of their own, which stay the same even when
+/// another thread starts measuring the scope, but don't match the
+/// ids in [`thread_snapshots`](fn.thread_snapshots.html).
 ///
 /// **Warning**: This function is pretty heavy, especially as the
-/// amount of samples rises, as it clones every one of them.
-pub(crate) fn get_measures() -> Vec<Measurement> {
-    let stack = MEASUREMENT_STACK.lock().unwrap();
-    let root = stack.get(0).unwrap().get_mut();
-    root.collect_all_children()
+/// amount of samples rises, as it copies every one of them.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// use std::thread;
+///
+/// {
+///     perf_measure!("setup");
+/// }
+/// thread::spawn(|| {
+///     perf_measure!("job");
+/// })
+/// .join()
+/// .unwrap();
+/// let before = stperf::snapshot().node_at_path(&["job"]).unwrap().id;
+///
+/// // Measured in the main thread, which started measuring first.
+/// {
+///     perf_measure!("job");
+/// }
+/// let snapshot = stperf::snapshot();
+/// let job = snapshot.node_at_path(&["job"]).unwrap();
+/// assert_eq!(job.id, before);
+/// assert_eq!(job.samples.len(), 2);
+/// # }
+/// ```
+pub fn snapshot() -> ProfileSnapshot {
+    let trees = get_thread_roots().iter().map(collect_tree).collect();
+    ProfileSnapshot::new(merge_trees(trees), *SESSION_START.lock().unwrap())
+}
+
+/// Returns a [`ProfileSnapshot`](struct.ProfileSnapshot.html) of
+/// each thread's measurements, in the order the threads started
+/// measuring. The snapshots are labeled with the threads' names, see
+/// [`ProfileSnapshot::thread_name`](struct.ProfileSnapshot.html#method.thread_name).
+/// Unnamed threads are labeled with their
+/// [`ThreadId`](https://doc.rust-lang.org/std/thread/struct.ThreadId.html)s.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// use std::thread;
+///
+/// thread::Builder::new()
+///     .name("render".to_string())
+///     .spawn(|| {
+///         perf_measure!("draw");
+///     })
+///     .unwrap()
+///     .join()
+///     .unwrap();
+///
+/// let snapshots = stperf::thread_snapshots();
+/// assert_eq!(snapshots[0].thread_name(), Some("render"));
+/// # }
+/// ```
+pub fn thread_snapshots() -> Vec<ProfileSnapshot> {
+    let trees: Vec<(MeasurementRef, String)> = {
+        let trees = THREAD_TREES.lock().unwrap();
+        trees
+            .iter()
+            .map(|tree| (tree.root.clone(), tree.name.clone()))
+            .collect()
+    };
+    let session_start = *SESSION_START.lock().unwrap();
+    trees
+        .into_iter()
+        .map(|(root, name)| {
+            let nodes = collect_tree(&root)
+                .into_iter()
+                .map(|(node, _)| node)
+                .collect();
+            ProfileSnapshot::new(nodes, session_start).with_thread_name(name)
+        })
+        .collect()
+}
+
+/// Returns the scopes under `root` as `ScopeNode`s, along with the
+/// scope ids they were measured with.
+fn collect_tree(root: &MeasurementRef) -> Vec<(ScopeNode, Option<ScopeId>)> {
+    let root = root.get_mut();
+    let mut nodes = Vec::new();
+    for (child, &scope_id) in root.children.iter().zip(&root.children_scope_ids) {
+        child.get_mut().collect_nodes(None, scope_id, &mut nodes);
+    }
+    nodes
+}
+
+/// Merges the trees of scopes measured in different threads into
+/// one, combining the scopes that would be the same scope if they
+/// were measured in the same thread: scopes with the same parent,
+/// and the same scope id, or the same name if either was measured
+/// with a string.
+fn merge_trees(trees: Vec<Vec<(ScopeNode, Option<ScopeId>)>>) -> Vec<ScopeNode> {
+    let mut ids_by_path = MERGED_IDS.lock().unwrap();
+    let mut merged: Vec<(ScopeNode, Option<ScopeId>)> = Vec::new();
+    // The indices of the merged scopes, by their ids.
+    let mut indices: HashMap<usize, usize> = HashMap::new();
+    let mut roots: Vec<usize> = Vec::new();
+    for tree in trees {
+        // The ids of the merged scopes, by the ids in this tree.
+        let mut merged_ids: HashMap<usize, usize> = HashMap::new();
+        for (mut node, scope_id) in tree {
+            let parent = node.parent.map(|parent| merged_ids[&parent]);
+            let siblings = match parent {
+                Some(parent) => &merged[indices[&parent]].0.children,
+                None => &roots,
+            };
+            let same_scope = siblings.iter().cloned().find(|id| {
+                let (ref sibling, sibling_scope_id) = merged[indices[id]];
+                match (scope_id, sibling_scope_id) {
+                    (Some(a), Some(b)) => a == b,
+                    _ => sibling.name == node.name,
+                }
+            });
+            if let Some(id) = same_scope {
+                merged_ids.insert(node.id, id);
+                merged[indices[&id]].0.combine(node);
+                continue;
+            }
+
+            let path = (parent, scope_id, node.name.clone());
+            let id = *ids_by_path
+                .entry(path)
+                .or_insert_with(|| NEXT_ID.fetch_add(1, Ordering::Relaxed));
+            merged_ids.insert(node.id, id);
+            match parent {
+                Some(parent) => merged[indices[&parent]].0.children.push(id),
+                None => roots.push(id),
+            }
+            node.id = id;
+            node.parent = parent;
+            node.children.clear();
+            indices.insert(id, merged.len());
+            merged.push((node, scope_id));
+        }
+    }
+    merged.into_iter().map(|(node, _)| node).collect()
+}
+
+/// Returns the fraction of the root-level scopes' time that is
+/// covered by the scopes directly under them, see
+/// [`ProfileSnapshot::coverage`](struct.ProfileSnapshot.html#method.coverage).
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// # use std::thread; use std::time::Duration;
+/// {
+///     perf_measure!("main");
+///     {
+///         perf_measure!("update");
+///         thread::sleep(Duration::from_millis(10));
+///     }
+///     thread::sleep(Duration::from_millis(10)); // Not measured!
+/// }
+/// assert!(stperf::coverage() < 0.9);
+/// # }
+/// ```
+pub fn coverage() -> f64 {
+    snapshot().coverage()
+}
+
+/// Returns the critical path through the measurements taken so far,
+/// see
+/// [`ProfileSnapshot::critical_path`](struct.ProfileSnapshot.html#method.critical_path).
+pub fn critical_path() -> Vec<(Vec<String>, f64)> {
+    snapshot().critical_path()
+}
+
+/// Returns a hash of the current measurement trees, like
+/// [`ProfileSnapshot::tree_hash`](struct.ProfileSnapshot.html#method.tree_hash),
+/// except the measurements are hashed where they are, without
+/// copying them into a snapshot first. The threads' trees are hashed
+/// separately instead of merged, so the hash can't be compared to
+/// the hash of a snapshot, only to earlier results of this function.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// {
+///     perf_measure!("main");
+/// }
+/// let before = stperf::tree_hash();
+/// assert_eq!(stperf::tree_hash(), before);
+/// {
+///     perf_measure!("main");
+/// }
+/// assert_ne!(stperf::tree_hash(), before);
+/// # }
+/// ```
+pub fn tree_hash() -> u64 {
+    let mut hasher = DefaultHasher::new();
+    // These change the durations of the snapshots, like the samples.
+    CALIBRATED_OVERHEAD
+        .load(Ordering::Relaxed)
+        .hash(&mut hasher);
+    (overhead_policy() == OverheadPolicy::Own).hash(&mut hasher);
+    for root in get_thread_roots() {
+        root.get_mut().hash_children(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Returns the ratio of the total times of the scopes at `path_a` and
+/// `path_b`, see
+/// [`ProfileSnapshot::ratio`](struct.ProfileSnapshot.html#method.ratio).
+pub fn ratio(path_a: &[&str], path_b: &[&str]) -> Option<f64> {
+    snapshot().ratio(path_a, path_b)
+}
+
+/// Returns how much time per root iteration would be saved by not
+/// measuring the scope at the end of `path`, see
+/// [`ProfileSnapshot::estimated_savings`](struct.ProfileSnapshot.html#method.estimated_savings).
+/// Returns zero if there is no such scope. Ranking the scopes by
+/// this tells where removing measurements helps the most.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// let overhead = stperf::calibrate_overhead();
+/// for _ in 0..10 {
+///     perf_measure!("main");
+///     {
+///         perf_measure!("tiny");
+///     }
+/// }
+/// // "tiny" was measured once per iteration.
+/// assert_eq!(stperf::estimated_savings(&["main", "tiny"]), overhead);
+/// # }
+/// ```
+pub fn estimated_savings(path: &[&str]) -> Duration {
+    snapshot()
+        .estimated_savings(path)
+        .unwrap_or_else(|| Duration::new(0, 0))
+}
+
+/// Returns the longest single sample measured so far, in
+/// nanoseconds, and the path of names to the scope it was measured
+/// in. Returns `None` if nothing has been measured. The sample is
+/// kept over [`reset_interval`](fn.reset_interval.html).
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// # use std::thread; use std::time::Duration;
+/// for i in 0..3 {
+///     perf_measure!("main");
+///     let ms = if i == 1 { 20 } else { 1 };
+///     {
+///         perf_measure!("broadphase");
+///         thread::sleep(Duration::from_millis(ms));
+///     }
+/// }
+/// let (path, _nanoseconds) = stperf::worst_sample().unwrap();
+/// assert_eq!(path, vec!["main"]);
+/// # }
+/// ```
+pub fn worst_sample() -> Option<(Vec<String>, u64)> {
+    let mut worst = None;
+    for root in get_thread_roots() {
+        for child in root.get_mut().children() {
+            child
+                .get_mut()
+                .find_worst_sample(&mut Vec::new(), &mut worst);
+        }
+    }
+    worst
 }
 
 #[derive(Clone, Debug)]
@@ -91,15 +1116,11 @@ pub(crate) struct MeasurementRef {
 }
 
 impl MeasurementRef {
-    pub(crate) fn get_mut(&self) -> MutexGuard<Measurement> {
-        match self.reference.try_lock() {
-            Ok(measurement) => measurement,
-            Err(err) => panic!("Failed to lock measurement! {}", err),
-        }
-    }
-
-    fn try_get_mut(&self) -> TryLockResult<MutexGuard<Measurement>> {
-        self.reference.try_lock()
+    /// Locks the measurement. The locks are always taken from the
+    /// root towards the leaves, so other threads reading the tree
+    /// can't deadlock with the thread measuring into it.
+    pub(crate) fn get_mut(&self) -> MutexGuard<'_, Measurement> {
+        self.reference.lock().unwrap()
     }
 }
 
@@ -112,75 +1133,133 @@ impl From<Arc<Mutex<Measurement>>> for MeasurementRef {
 /// Represents a scope's running time.
 #[derive(Clone, Debug)]
 pub(crate) struct Measurement {
+    pub(crate) id: usize,
     pub(crate) name: String,
     pub(crate) depth: usize,
     pub(crate) overhead: Duration,
+    /// The largest change in the resident set size during a single
+    /// sample, in bytes, see the `rss`-feature.
+    pub(crate) rss_growth: Option<i64>,
     pub(crate) durations: Vec<Duration>,
-    pub(crate) parent: Option<MeasurementRef>,
+    /// The root iterations the `durations` were recorded in.
+    pub(crate) iterations: Vec<usize>,
+    /// The longest sample, kept over `reset_interval`.
+    lifetime_max: Option<Duration>,
+    /// How many times the measurement has been started.
+    activations: usize,
+    /// The parent's `activations` when this measurement was last
+    /// started, for counting the distinct children in
+    /// `active_children`.
+    parent_activation: Option<usize>,
+    /// The distinct children measured during the current sample.
+    active_children: usize,
+    /// The largest `active_children` of a single sample.
+    max_child_count: usize,
     children: Vec<MeasurementRef>,
-    children_names: Vec<String>,
+    /// The [`ScopeName`](trait.ScopeName.html) ids of the
+    /// `children`, for the ones measured with one.
+    children_scope_ids: Vec<Option<ScopeId>>,
     measuring_currently: bool,
 }
 
 impl Measurement {
-    fn new(name: String, depth: usize, parent: Option<MeasurementRef>) -> MeasurementRef {
+    #[allow(clippy::new_ret_no_self)]
+    /// Creates a new measurement, the root if `depth` is 0.
+    fn new(name: String, depth: usize) -> MeasurementRef {
+        let id = if depth > 0 {
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        } else {
+            0
+        };
         MeasurementRef::from(Arc::new(Mutex::new(Measurement {
+            id,
             name,
             depth,
             overhead: Duration::new(0, 0),
+            rss_growth: None,
             durations: Vec::new(),
-            parent: parent.into(),
+            iterations: Vec::new(),
+            lifetime_max: None,
+            activations: 0,
+            parent_activation: None,
+            active_children: 0,
+            max_child_count: 0,
             children: Vec::new(),
-            children_names: Vec::new(),
+            children_scope_ids: Vec::new(),
             measuring_currently: true,
         })))
     }
 
-    pub(crate) fn get_ancestor(&self, generation: u32) -> Option<MeasurementRef> {
-        if generation == 0 {
-            if let Some(ref parent) = self.parent {
-                Some(MeasurementRef::from(parent.clone()))
-            } else {
-                None
-            }
-        } else {
-            if let Some(ref parent) = self.parent {
-                let parent = parent.get_mut();
-                parent.get_ancestor(generation - 1)
-            } else {
-                None
-            }
-        }
-    }
-
-    pub(crate) fn has_children(&self) -> bool {
-        self.children.len() > 0
+    pub(crate) fn children(&self) -> &[MeasurementRef] {
+        &self.children
     }
 
-    /// Is `name` the last child of `self`?
-    pub(crate) fn is_last_child_name(&self, name: &str, leaf: bool) -> bool {
-        if let Some(last_leaf_name) = self.last_child_name(leaf) {
-            last_leaf_name == name
-        } else {
-            false
+    /// Hashes the children of this measurement, and the ones under
+    /// them, for `tree_hash`.
+    fn hash_children(&self, hasher: &mut DefaultHasher) {
+        // The count separates the siblings from the scopes under them.
+        self.children.len().hash(hasher);
+        for child in &self.children {
+            let child = child.get_mut();
+            child.name.hash(hasher);
+            child.durations.len().hash(hasher);
+            saturating_sum(child.durations.iter().cloned().map(to_nanos)).hash(hasher);
+            child.overhead.hash(hasher);
+            child.hash_children(hasher);
         }
     }
 
-    fn last_child_name(&self, leaf: bool) -> Option<String> {
-        if leaf && self.children.len() == 0 {
-            None
-        } else {
-            Some(self.children_names[self.children.len() - 1].clone())
+    /// Appends this measurement and the ones under it to `nodes` as
+    /// `ScopeNode`s, along with their scope ids. `scope_id` is this
+    /// measurement's scope id in its parent.
+    fn collect_nodes(
+        &self,
+        parent: Option<usize>,
+        scope_id: Option<ScopeId>,
+        nodes: &mut Vec<(ScopeNode, Option<ScopeId>)>,
+    ) {
+        let index = nodes.len();
+        let node = ScopeNode {
+            id: self.id,
+            name: self.name.clone(),
+            depth: self.depth,
+            parent,
+            children: Vec::new(),
+            samples: self.durations.iter().cloned().map(to_nanos).collect(),
+            iterations: self.iterations.clone(),
+            overhead: self.get_own_overhead_ns(),
+            duration: self.get_duration_ns(),
+            rss_growth: self.rss_growth,
+            lifetime_max: self.lifetime_max.map(to_nanos),
+            max_child_count: self.max_child_count,
+        };
+        nodes.push((node, scope_id));
+        for (child, &child_scope_id) in self.children.iter().zip(&self.children_scope_ids) {
+            let child = child.get_mut();
+            nodes[index].0.children.push(child.id);
+            child.collect_nodes(Some(self.id), child_scope_id, nodes);
         }
     }
 
-    pub(crate) fn collect_all_children(&self) -> Vec<Measurement> {
-        let mut collection = Vec::new();
-        collection.push(self.clone());
+    /// Replaces `worst` with the longest sample of this measurement
+    /// or the ones under it, if it's longer. `path` is the path to
+    /// this measurement's parent.
+    fn find_worst_sample(&self, path: &mut Vec<String>, worst: &mut Option<(Vec<String>, u64)>) {
+        path.push(self.name.clone());
+        if let Some(longest) = self.lifetime_max {
+            let longest = to_nanos(longest);
+            let is_worst = match *worst {
+                Some((_, worst_duration)) => longest > worst_duration,
+                None => true,
+            };
+            if is_worst {
+                *worst = Some((path.clone(), longest));
+            }
+        }
         for child in &self.children {
-            collection.append(&mut child.get_mut().collect_all_children());
+            child.get_mut().find_worst_sample(path, worst);
         }
-        collection
+        path.pop();
     }
 
     pub(crate) fn get_duration_ns(&self) -> Option<u64> {
@@ -188,43 +1267,60 @@ impl Measurement {
         if count == 0 {
             None
         } else {
-            let mut total: u64 = 0;
-            for duration in &self.durations {
-                total += duration.subsec_nanos() as u64;
-            }
-            if total < self.get_overhead_ns() {
-                // This should never happen, but technically it's possible.
-                Some(0)
-            } else {
-                Some(total - self.get_overhead_ns())
-            }
+            // Saturating, so a very long session can't wrap around.
+            let total = saturating_sum(self.durations.iter().cloned().map(to_nanos));
+            let overhead = match overhead_policy() {
+                OverheadPolicy::Own => self.get_own_overhead_ns(),
+                OverheadPolicy::Inclusive => self.get_overhead_ns(),
+            };
+            // The overhead should never be larger than the total, but
+            // technically it's possible.
+            Some(total.saturating_sub(overhead))
         }
     }
 
-    pub(crate) fn get_overhead_ns(&self) -> u64 {
-        let mut overhead =
-            self.overhead.as_secs() * 1_000_000_000 + self.overhead.subsec_nanos() as u64;
-        for child in &self.children {
-            if let Ok(child) = child.try_get_mut() {
-                overhead += child.get_overhead_ns();
-            }
+    /// Returns the overhead of measuring this scope, without the
+    /// scopes under it. This is the calibrated overhead for every
+    /// sample if [`calibrate_overhead`](fn.calibrate_overhead.html)
+    /// has been called, and the captured overhead otherwise.
+    fn get_own_overhead_ns(&self) -> u64 {
+        match CALIBRATED_OVERHEAD.load(Ordering::Relaxed) {
+            0 => to_nanos(self.overhead),
+            overhead => (overhead as u64).saturating_mul(self.durations.len() as u64),
         }
-        overhead
     }
 
-    fn collect_all_children_arc(&self) -> Vec<MeasurementRef> {
-        let mut collection = Vec::new();
-        for child in &self.children {
-            collection.push(child.clone());
-            let child = child.get_mut();
-            collection.append(&mut child.collect_all_children_arc());
+    pub(crate) fn get_overhead_ns(&self) -> u64 {
+        let children_overhead = self
+            .children
+            .iter()
+            .map(|child| child.get_mut().get_overhead_ns());
+        saturating_sum(children_overhead).saturating_add(self.get_own_overhead_ns())
+    }
+
+    /// Returns the child measured with `scope_id`. If there isn't
+    /// one, a child with the same `name` that has only been measured
+    /// with a string is returned, and it's given the `scope_id`.
+    fn get_scope_child(&mut self, scope_id: ScopeId, name: &str) -> Option<MeasurementRef> {
+        if let Some(i) = self
+            .children_scope_ids
+            .iter()
+            .position(|&id| id == Some(scope_id))
+        {
+            return Some(self.children[i].clone());
+        }
+        for (child, id) in self.children.iter().zip(self.children_scope_ids.iter_mut()) {
+            if id.is_none() && child.get_mut().name == name {
+                *id = Some(scope_id);
+                return Some(child.clone());
+            }
         }
-        collection
+        None
     }
 
-    fn get_child(&mut self, name: &str) -> Option<MeasurementRef> {
+    fn get_child(&self, name: &str) -> Option<MeasurementRef> {
         for child in &self.children {
-            let mut child_lock = child.get_mut();
+            let child_lock = child.get_mut();
             let child_name = child_lock.name.clone();
             if child_name == name {
                 return Some(child.clone());
@@ -233,29 +1329,100 @@ impl Measurement {
         None
     }
 
-    fn remove_locked_child(&mut self) {
-        let children = &mut self.children;
-        let mut remove_index = None;
-        for i in 0..children.len() {
-            if let Err(_) = children[i].try_get_mut() {
-                remove_index = Some(i);
-                break;
-            }
-        }
-        if let Some(i) = remove_index {
-            children.remove(i);
+    /// Adds the scopes under `other` to the ones under this
+    /// measurement, without their samples, and keeps the longer
+    /// `lifetime_max` of the scopes that are under both. See
+    /// `fold_exited_trees`.
+    fn fold_lifetimes(&mut self, other: &Measurement) {
+        for (other_child, &scope_id) in other.children.iter().zip(&other.children_scope_ids) {
+            let other_child = other_child.get_mut();
+            let existing = match scope_id {
+                Some(scope_id) => self.get_scope_child(scope_id, &other_child.name),
+                None => self.get_child(&other_child.name),
+            };
+            let child = existing.unwrap_or_else(|| {
+                let child = Measurement::new(other_child.name.clone(), other_child.depth);
+                child.get_mut().measuring_currently = false;
+                self.children.push(child.clone());
+                self.children_scope_ids.push(scope_id);
+                child
+            });
+            let mut child = child.get_mut();
+            child.lifetime_max = child.lifetime_max.max(other_child.lifetime_max);
+            child.fold_lifetimes(&other_child);
         }
     }
 
-    fn remove_while_locked(&self) {
-        if let Some(ref parent) = self.parent {
-            let mut parent = parent.get_mut();
-            parent.remove_locked_child();
+    /// Removes the children that aren't being measured currently,
+    /// and clears the durations of the ones that are, recursively. If
+    /// `keep_lifetime` is true, all the children are kept, along
+    /// with their `lifetime_max`.
+    fn reset_children(&mut self, keep_lifetime: bool) {
+        let mut i = 0;
+        while i < self.children.len() {
+            let kept = {
+                let mut child = self.children[i].get_mut();
+                if child.measuring_currently || keep_lifetime {
+                    child.clear_durations();
+                    if !keep_lifetime {
+                        child.lifetime_max = None;
+                    }
+                    child.reset_children(keep_lifetime);
+                }
+                child.measuring_currently || keep_lifetime
+            };
+            if kept {
+                i += 1;
+            } else {
+                self.children.remove(i);
+                self.children_scope_ids.remove(i);
+            }
         }
     }
 
     fn clear_durations(&mut self) {
         self.durations.clear();
+        self.iterations.clear();
         self.overhead = Duration::new(0, 0);
+        self.rss_growth = None;
+        self.max_child_count = 0;
+    }
+
+    /// Marks the measurement as started during the parent's
+    /// `parent_activation`th sample. Returns true if it hadn't been
+    /// started during that sample yet.
+    fn start_activation(&mut self, parent_activation: usize) -> bool {
+        self.measuring_currently = true;
+        self.activations += 1;
+        self.active_children = 0;
+        let first = self.parent_activation != Some(parent_activation);
+        self.parent_activation = Some(parent_activation);
+        first
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_saturate_instead_of_wrapping() {
+        let half = Duration::from_nanos(u64::MAX / 2 + 1);
+        let child = Measurement::new("child".to_string(), 2);
+        {
+            let mut child = child.get_mut();
+            child.durations = vec![half, half];
+            child.overhead = Duration::from_nanos(1000);
+        }
+        assert_eq!(child.get_mut().get_duration_ns(), Some(u64::MAX - 1000));
+
+        let parent = Measurement::new("parent".to_string(), 1);
+        let mut parent = parent.get_mut();
+        parent.durations = vec![half];
+        parent.overhead = Duration::from_nanos(u64::MAX);
+        parent.children.push(child);
+        parent.children_scope_ids.push(None);
+        assert_eq!(parent.get_overhead_ns(), u64::MAX);
+        assert_eq!(parent.get_duration_ns(), Some(0));
     }
 }
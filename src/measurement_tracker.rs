@@ -1,8 +1,68 @@
+use std::marker::PhantomData;
 use std::time::{Duration, Instant};
 
 /// Represents a started measurement. When dropped, it will log the
-/// duration into memory.
+/// duration into memory. It has to be dropped in the thread it was
+/// started in.
+///
+/// The trackers have to be dropped in the reverse order they were
+/// created in, which happens automatically when they're kept in
+/// local variables, like [`perf_measure!`](macro.perf_measure.html)
+/// does. In debug builds, trackers that are never dropped (eg.
+/// because of `mem::forget`) are noticed when the tracker around
+/// them is dropped, and a warning is printed. Their measurements are
+/// left unfinished, so the following measurements still end up in
+/// the right place. Leaked root-level trackers have nothing around
+/// them, so they're noticed when their thread exits instead.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; #[cfg(not(feature = "disabled"))] fn main() {
+/// use std::mem;
+/// use std::time::Instant;
+///
+/// {
+///     perf_measure!("main");
+///     mem::forget(stperf::measure(Instant::now(), "leaked"));
+/// }
+/// {
+///     // Warns about "leaked" in debug builds, and is measured as a
+///     // root-level scope instead of ending up under "leaked".
+///     perf_measure!("render");
+/// }
+/// # }
+/// # #[cfg(feature = "disabled")] fn main() {}
+/// ```
+///
+/// ```
+/// # #[cfg(not(feature = "disabled"))] fn main() {
+/// use std::mem;
+/// use std::thread;
+/// use std::time::Instant;
+///
+/// thread::spawn(|| {
+///     mem::forget(stperf::measure(Instant::now(), "job"));
+///     // Warns about "job" in debug builds when the thread exits.
+/// })
+/// .join()
+/// .unwrap();
+/// # }
+/// # #[cfg(feature = "disabled")] fn main() {}
+/// ```
 pub struct MeasurementTracker {
     pub(crate) start_time: Instant,
     pub(crate) overhead: Duration,
+    /// Was the measurement recorded? If not, dropping this does
+    /// nothing.
+    pub(crate) recording: bool,
+    /// Is this a root-level measurement?
+    pub(crate) root_level: bool,
+    /// The resident set size at the start of the measurement, see
+    /// the `rss`-feature.
+    pub(crate) start_rss: Option<u64>,
+    /// The measurement's index in the thread's measurement stack, for
+    /// noticing leaked trackers.
+    pub(crate) depth: usize,
+    /// Keeps the tracker in its thread, as it pops the thread's
+    /// measurement stack when dropped.
+    pub(crate) not_send: PhantomData<*const ()>,
 }
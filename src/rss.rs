@@ -0,0 +1,21 @@
+//! Reading the process's resident set size, for the `rss`-feature.
+
+#[cfg(target_os = "linux")]
+use std::fs;
+
+/// Returns the resident set size of the process in bytes, or `None`
+/// if it can't be read on this platform.
+#[cfg(target_os = "linux")]
+pub(crate) fn resident_set_size() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Returns the resident set size of the process in bytes, or `None`
+/// if it can't be read on this platform.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn resident_set_size() -> Option<u64> {
+    None
+}
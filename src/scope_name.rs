@@ -0,0 +1,133 @@
+/// A name for a scope, for measuring with
+/// [`perf_measure_enum!`](macro.perf_measure_enum.html) instead of
+/// strings. Usually implemented by an enum with a variant for every
+/// scope, so that typos in the names are caught by the compiler.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; fn main() {
+/// use stperf::ScopeName;
+///
+/// enum Scope {
+///     Physics,
+///     Rendering,
+/// }
+///
+/// impl ScopeName for Scope {
+///     fn name(&self) -> &'static str {
+///         match *self {
+///             Scope::Physics => "physics",
+///             Scope::Rendering => "rendering",
+///         }
+///     }
+///
+///     fn id(&self) -> u32 {
+///         match *self {
+///             Scope::Physics => 0,
+///             Scope::Rendering => 1,
+///         }
+///     }
+/// }
+///
+/// {
+///     perf_measure_enum!(Scope::Physics);
+/// }
+/// {
+///     perf_measure_enum!(Scope::Rendering);
+/// }
+/// # }
+/// ```
+///
+/// The ids only need to be unique within one type: scopes of
+/// different `ScopeName` types are told apart even if their ids are
+/// the same. A string scope and a `ScopeName` with the same name are
+/// the same scope, whichever is measured first. Two different
+/// `ScopeName`s with the same name, on the other hand, are separate
+/// scopes that just show up with the same name.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; #[cfg(not(feature = "disabled"))] fn main() {
+/// use stperf::ScopeName;
+///
+/// struct Physics;
+/// struct Rendering;
+///
+/// impl ScopeName for Physics {
+///     fn name(&self) -> &'static str { "physics" }
+///     fn id(&self) -> u32 { 0 }
+/// }
+///
+/// impl ScopeName for Rendering {
+///     fn name(&self) -> &'static str { "rendering" }
+///     fn id(&self) -> u32 { 0 }
+/// }
+///
+/// {
+///     perf_measure!("physics");
+/// }
+/// {
+///     perf_measure_enum!(Physics);
+/// }
+/// {
+///     perf_measure_enum!(Rendering);
+/// }
+///
+/// let snapshot = stperf::snapshot();
+/// let physics = snapshot.node_at_path(&["physics"]).unwrap();
+/// let rendering = snapshot.node_at_path(&["rendering"]).unwrap();
+/// assert_eq!(physics.samples.len(), 2);
+/// assert_eq!(rendering.samples.len(), 1);
+/// # }
+/// # #[cfg(feature = "disabled")] fn main() {}
+/// ```
+///
+/// The scopes are told apart the same way when the measurements of
+/// different threads are merged together in
+/// [`snapshot`](fn.snapshot.html):
+///
+/// ```
+/// # #[macro_use] extern crate stperf; #[cfg(not(feature = "disabled"))] fn main() {
+/// use std::thread;
+/// use stperf::ScopeName;
+///
+/// struct Physics;
+/// struct Rendering;
+///
+/// impl ScopeName for Physics {
+///     fn name(&self) -> &'static str { "step" }
+///     fn id(&self) -> u32 { 0 }
+/// }
+///
+/// impl ScopeName for Rendering {
+///     fn name(&self) -> &'static str { "step" }
+///     fn id(&self) -> u32 { 0 }
+/// }
+///
+/// for _ in 0..2 {
+///     thread::spawn(|| {
+///         {
+///             perf_measure_enum!(Physics);
+///         }
+///         {
+///             perf_measure_enum!(Rendering);
+///         }
+///     })
+///     .join()
+///     .unwrap();
+/// }
+///
+/// let snapshot = stperf::snapshot();
+/// assert_eq!(snapshot.roots().len(), 2);
+/// for &root in snapshot.roots() {
+///     assert_eq!(snapshot.node(root).unwrap().samples.len(), 2);
+/// }
+/// # }
+/// # #[cfg(feature = "disabled")] fn main() {}
+/// ```
+pub trait ScopeName {
+    /// The name shown in the output.
+    fn name(&self) -> &'static str;
+    /// An id which is different for every scope name of the type.
+    /// Scopes are looked up by this instead of the name, which is
+    /// faster than comparing strings.
+    fn id(&self) -> u32;
+}
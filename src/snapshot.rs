@@ -0,0 +1,1126 @@
+//! A copy of the measurement tree, for inspecting the data without
+//! formatting it.
+
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, SystemTime};
+
+/// The version of the exported formats, see
+/// [`ProfileSnapshot::to_ron`](struct.ProfileSnapshot.html#method.to_ron).
+/// Bumped when the exported fields change between releases.
+const EXPORT_VERSION: u32 = 1;
+
+/// A copy of the measurement data at some point in time, see
+/// [`snapshot`](fn.snapshot.html).
+///
+/// The scopes are stored as [`ScopeNode`](struct.ScopeNode.html)s,
+/// which refer to each other by their ids.
+///
+/// ```
+/// # #[macro_use] extern crate stperf; #[cfg(not(feature = "disabled"))] fn main() {
+/// {
+///     perf_measure!("main");
+///     {
+///         perf_measure!("physics");
+///     }
+/// }
+///
+/// let snapshot = stperf::snapshot();
+/// let main = snapshot.node(snapshot.roots()[0]).unwrap();
+/// assert_eq!(main.name, "main");
+/// let physics = &snapshot.children_of(main.id)[0];
+/// assert_eq!(physics.name, "physics");
+/// assert_eq!(snapshot.parent_of(physics.id).unwrap().id, main.id);
+/// # }
+/// # #[cfg(feature = "disabled")] fn main() {}
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ProfileSnapshot {
+    nodes: Vec<ScopeNode>,
+    roots: Vec<usize>,
+    indices: HashMap<usize, usize>,
+    session_start: Option<SystemTime>,
+    thread_name: Option<String>,
+}
+
+/// A single scope in a [`ProfileSnapshot`](struct.ProfileSnapshot.html).
+#[derive(Clone, Debug)]
+pub struct ScopeNode {
+    /// The id of the scope. Ids stay the same across snapshots, until
+    /// the scope is removed by [`reset`](fn.reset.html).
+    pub id: usize,
+    /// The name given to
+    /// [`perf_measure!`](macro.perf_measure.html).
+    pub name: String,
+    /// How deep the scope is, root-level scopes have a depth of 1.
+    pub depth: usize,
+    /// The id of the parent scope, `None` for root-level scopes.
+    pub parent: Option<usize>,
+    /// The ids of the child scopes, in the order they were first
+    /// measured.
+    pub children: Vec<usize>,
+    /// The durations of the samples, in nanoseconds.
+    pub samples: Vec<u64>,
+    /// The root iterations the `samples` were recorded in, see
+    /// [`frame_mark`](fn.frame_mark.html).
+    pub iterations: Vec<usize>,
+    /// The overhead of measuring this scope, in nanoseconds. See
+    /// [`calibrate_overhead`](fn.calibrate_overhead.html).
+    pub overhead: u64,
+    /// The total duration of the samples, with the overhead of this
+    /// scope and the scopes under it subtracted, in nanoseconds. See
+    /// [`OverheadPolicy`](enum.OverheadPolicy.html) for only
+    /// subtracting this scope's own overhead.
+    /// `None` if there are no samples. If the total doesn't fit in a
+    /// `u64`, it's `u64::MAX`, instead of wrapping around.
+    ///
+    /// ```
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main", "item_a"], &[u64::MAX / 2, u64::MAX / 2])
+    ///     .add_scope(&["main", "item_b"], &[u64::MAX / 2])
+    ///     .build();
+    /// let item_a = snapshot.node_at_path(&["main", "item_a"]).unwrap();
+    /// assert_eq!(item_a.duration, Some(u64::MAX - 1));
+    ///
+    /// let merged = snapshot.merge_matching("item_*", "items", false);
+    /// let items = merged.node_at_path(&["main", "items"]).unwrap();
+    /// assert_eq!(items.duration, Some(u64::MAX));
+    /// assert_eq!(merged.self_time(items.id), Some(u64::MAX));
+    /// ```
+    ///
+    /// If the overhead is larger than the samples, the duration is 0
+    /// instead of wrapping around. Here the samples are started in
+    /// the future, so they're empty, and only the overhead is left:
+    ///
+    /// ```
+    /// # #[cfg(not(feature = "disabled"))] fn main() {
+    /// use std::time::{Duration, Instant};
+    ///
+    /// stperf::calibrate_overhead();
+    /// let future = Instant::now() + Duration::from_secs(60);
+    /// {
+    ///     let _main = stperf::measure(future, "main");
+    ///     let _update = stperf::measure(future, "update");
+    /// }
+    ///
+    /// let snapshot = stperf::snapshot();
+    /// let main = snapshot.node_at_path(&["main"]).unwrap();
+    /// let update = snapshot.node_at_path(&["main", "update"]).unwrap();
+    /// assert_eq!(main.samples, vec![0]);
+    /// assert!(main.overhead > 0 && update.overhead > 0);
+    /// assert_eq!(main.duration, Some(0));
+    /// assert_eq!(update.duration, Some(0));
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub duration: Option<u64>,
+    /// The largest growth of the process's resident set size during
+    /// a single sample, in bytes. `None` unless the `rss`-feature is
+    /// enabled and the platform supports it.
+    pub rss_growth: Option<i64>,
+    /// The longest sample, in nanoseconds. Unlike the samples, this
+    /// is kept over [`reset_interval`](fn.reset_interval.html).
+    /// `None` if the scope hasn't been measured.
+    pub lifetime_max: Option<u64>,
+    /// The most distinct scopes measured directly under this scope
+    /// during a single sample of it. Compared to the number of
+    /// `children`, this tells how much the scope's structure changes
+    /// from one sample to another.
+    ///
+    /// ```
+    /// # #[macro_use] extern crate stperf; #[cfg(not(feature = "disabled"))] fn main() {
+    /// for i in 0..4 {
+    ///     perf_measure!("update");
+    ///     if i % 2 == 0 {
+    ///         perf_measure!("physics");
+    ///     } else {
+    ///         perf_measure!("ai");
+    ///     }
+    /// }
+    /// let snapshot = stperf::snapshot();
+    /// let update = snapshot.node_at_path(&["update"]).unwrap();
+    /// assert_eq!(update.children.len(), 2);
+    /// assert_eq!(update.max_child_count, 1);
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub max_child_count: usize,
+}
+
+impl ProfileSnapshot {
+    pub(crate) fn new(nodes: Vec<ScopeNode>, session_start: Option<SystemTime>) -> ProfileSnapshot {
+        let roots = nodes
+            .iter()
+            .filter(|node| node.parent.is_none())
+            .map(|node| node.id)
+            .collect();
+        let indices = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.id, i))
+            .collect();
+        ProfileSnapshot {
+            nodes,
+            roots,
+            indices,
+            session_start,
+            thread_name: None,
+        }
+    }
+
+    /// Sets the name of the thread the snapshot was measured in.
+    pub(crate) fn with_thread_name(mut self, thread_name: String) -> ProfileSnapshot {
+        self.thread_name = Some(thread_name);
+        self
+    }
+
+    /// Returns the name of the thread this snapshot was measured in,
+    /// for the snapshots returned by
+    /// [`thread_snapshots`](fn.thread_snapshots.html). `None` for
+    /// snapshots of every thread merged together.
+    pub fn thread_name(&self) -> Option<&str> {
+        self.thread_name.as_deref()
+    }
+
+    /// Returns the wall-clock time of the first measurement since
+    /// the start of the program, or the last
+    /// [`reset`](fn.reset.html). `None` if nothing has been measured
+    /// yet.
+    ///
+    /// ```
+    /// # #[macro_use] extern crate stperf; #[cfg(not(feature = "disabled"))] fn main() {
+    /// {
+    ///     perf_measure!("main");
+    /// }
+    /// assert!(stperf::snapshot().session_start().is_some());
+    /// stperf::reset();
+    /// assert!(stperf::snapshot().session_start().is_none());
+    /// # }
+    /// # #[cfg(feature = "disabled")] fn main() {}
+    /// ```
+    pub fn session_start(&self) -> Option<SystemTime> {
+        self.session_start
+    }
+
+    /// Returns all the scopes, parents before their children.
+    pub fn nodes(&self) -> &[ScopeNode] {
+        &self.nodes
+    }
+
+    /// Returns the ids of the root-level scopes.
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    /// Returns the scope with the given id.
+    pub fn node(&self, id: usize) -> Option<&ScopeNode> {
+        self.indices.get(&id).map(|&i| &self.nodes[i])
+    }
+
+    /// Returns the scope at the end of `path`, which goes from a
+    /// root-level scope to the scope itself.
+    pub fn node_at_path(&self, path: &[&str]) -> Option<&ScopeNode> {
+        let mut candidates: Vec<&ScopeNode> =
+            self.roots.iter().filter_map(|&id| self.node(id)).collect();
+        let mut found = None;
+        for name in path {
+            let node = candidates.into_iter().find(|node| node.name == *name)?;
+            candidates = self.children_of(node.id);
+            found = Some(node);
+        }
+        found
+    }
+
+    /// Returns the path of names from the root level to the scope
+    /// with the given id, ie. the path
+    /// [`node_at_path`](#method.node_at_path) finds it with. Returns
+    /// an empty `Vec` if there is no such scope.
+    pub fn path_of(&self, id: usize) -> Vec<String> {
+        if self.node(id).is_none() {
+            return Vec::new();
+        }
+        self.ancestors_of(id)
+            .into_iter()
+            .chain(Some(id))
+            .filter_map(|id| self.node(id))
+            .map(|node| node.name.clone())
+            .collect()
+    }
+
+    /// Returns the children of the scope with the given id. Returns
+    /// an empty `Vec` if there is no such scope.
+    pub fn children_of(&self, id: usize) -> Vec<&ScopeNode> {
+        match self.node(id) {
+            Some(node) => node
+                .children
+                .iter()
+                .filter_map(|&child| self.node(child))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the parent of the scope with the given id, `None` for
+    /// root-level scopes.
+    pub fn parent_of(&self, id: usize) -> Option<&ScopeNode> {
+        self.node(id)
+            .and_then(|node| node.parent)
+            .and_then(|parent| self.node(parent))
+    }
+
+    /// Returns the self-time of the scope with the given id: its
+    /// duration, minus the durations of its children, in
+    /// nanoseconds. This is the time spent in the scope which isn't
+    /// explained by any measured scope under it. Returns `None` if
+    /// there is no such scope, or it has no samples.
+    pub fn self_time(&self, id: usize) -> Option<u64> {
+        let duration = self.node(id)?.duration?;
+        let children_duration = saturating_sum(
+            self.children_of(id)
+                .iter()
+                .filter_map(|child| child.duration),
+        );
+        Some(duration.saturating_sub(children_duration))
+    }
+
+    /// Returns the fraction of the root-level scopes' time that is
+    /// covered by the scopes directly under them, between 0 and 1.
+    /// A low coverage means there's a lot of time in the root-level
+    /// scopes that isn't explained by any measured scope. Returns 0
+    /// if the root-level scopes don't have any samples.
+    pub fn coverage(&self) -> f64 {
+        let mut root_duration = 0;
+        let mut covered_duration = 0;
+        for &root in &self.roots {
+            if let Some(duration) = self.node(root).and_then(|node| node.duration) {
+                let children = self.children_of(root);
+                let children_duration =
+                    saturating_sum(children.iter().filter_map(|child| child.duration));
+                root_duration = duration.saturating_add(root_duration);
+                covered_duration = children_duration.saturating_add(covered_duration);
+            }
+        }
+        if root_duration == 0 {
+            0.0
+        } else {
+            (covered_duration as f64 / root_duration as f64).min(1.0)
+        }
+    }
+
+    /// Returns the internal scopes, ie. the ones with children, and
+    /// their self-times (see [`self_time`](#method.self_time)),
+    /// highest self-time first.
+    ///
+    /// An internal scope with a high self-time usually means there's
+    /// code inside it that could use more
+    /// [`perf_measure!`](macro.perf_measure.html)s, to explain where
+    /// the time goes.
+    ///
+    /// ```
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main"], &[10_000_000])
+    ///     .add_scope(&["main", "update"], &[6_000_000])
+    ///     .add_scope(&["main", "update", "physics"], &[5_000_000])
+    ///     .add_scope(&["main", "render"], &[1_000_000])
+    ///     .build();
+    /// let internal: Vec<(&str, u64)> = snapshot
+    ///     .internal_scopes_by_self_time()
+    ///     .into_iter()
+    ///     .map(|(node, self_time)| (node.name.as_str(), self_time))
+    ///     .collect();
+    /// assert_eq!(internal, vec![("main", 3_000_000), ("update", 1_000_000)]);
+    /// ```
+    pub fn internal_scopes_by_self_time(&self) -> Vec<(&ScopeNode, u64)> {
+        let mut internal_scopes: Vec<(&ScopeNode, u64)> = self
+            .nodes
+            .iter()
+            .filter(|node| !node.is_leaf())
+            .filter_map(|node| self.self_time(node.id).map(|self_time| (node, self_time)))
+            .collect();
+        internal_scopes.sort_by_key(|&(_, self_time)| Reverse(self_time));
+        internal_scopes
+    }
+
+    /// Returns the critical path: starting from the slowest
+    /// root-level scope, the chain of scopes found by always going
+    /// into the slowest child. Each scope is returned with its path
+    /// of names and the percentage of the root-level scope's time it
+    /// takes, so it reads like "main → update → physics is 78% of
+    /// main". Scopes without samples are skipped.
+    ///
+    /// ```
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main"], &[10_000_000])
+    ///     .add_scope(&["main", "update"], &[8_000_000])
+    ///     .add_scope(&["main", "update", "physics"], &[5_000_000])
+    ///     .add_scope(&["main", "update", "ai"], &[3_000_000])
+    ///     .add_scope(&["main", "render"], &[2_000_000])
+    ///     .build();
+    /// let path = snapshot.critical_path();
+    /// assert_eq!(path.len(), 3);
+    /// assert_eq!(path[2].0, vec!["main", "update", "physics"]);
+    /// assert_eq!(path[2].1, 50.0);
+    /// ```
+    pub fn critical_path(&self) -> Vec<(Vec<String>, f64)> {
+        let slowest = |ids: &[usize]| {
+            ids.iter()
+                .filter_map(|&id| self.node(id))
+                .filter_map(|node| node.duration.map(|duration| (node, duration)))
+                .max_by_key(|&(_, duration)| duration)
+        };
+        let mut critical_path = Vec::new();
+        let (mut node, root_duration) = match slowest(&self.roots) {
+            Some(root) => root,
+            None => return critical_path,
+        };
+        let mut path = Vec::new();
+        loop {
+            path.push(node.name.clone());
+            let duration = node.duration.unwrap_or(0);
+            let percent = if root_duration == 0 {
+                100.0
+            } else {
+                100.0 * (duration as f64 / root_duration as f64)
+            };
+            critical_path.push((path.clone(), percent));
+            match slowest(&node.children) {
+                Some((child, _)) => node = child,
+                None => return critical_path,
+            }
+        }
+    }
+
+    /// Returns the total time of the scope at `path_a` divided by the
+    /// total time of the scope at `path_b`, eg. 1.5 if `path_a` took
+    /// one and a half times as long. The totals are the scopes'
+    /// [`duration`](struct.ScopeNode.html#structfield.duration)s, so
+    /// a scope that's measured more often weighs more. Returns `None`
+    /// if either scope has no samples, or `path_b` took no time.
+    ///
+    /// ```
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main", "render"], &[3_000_000])
+    ///     .add_scope(&["main", "update"], &[1_000_000, 1_000_000])
+    ///     .build();
+    /// assert_eq!(snapshot.ratio(&["main", "render"], &["main", "update"]), Some(1.5));
+    /// assert_eq!(snapshot.ratio(&["main", "render"], &["main", "audio"]), None);
+    /// ```
+    pub fn ratio(&self, path_a: &[&str], path_b: &[&str]) -> Option<f64> {
+        let a = self.node_at_path(path_a)?.duration?;
+        let b = self.node_at_path(path_b)?.duration?;
+        if b == 0 {
+            None
+        } else {
+            Some(a as f64 / b as f64)
+        }
+    }
+
+    /// Returns how much time per root iteration (see
+    /// [`frame_mark`](fn.frame_mark.html)) would be saved by not
+    /// measuring the scope at the end of `path`: the overhead of
+    /// measuring it, divided by the amount of root iterations
+    /// measured. Returns `None` if there is no such scope.
+    pub fn estimated_savings(&self, path: &[&str]) -> Option<Duration> {
+        let node = self.node_at_path(path)?;
+        let iterations: BTreeSet<usize> = self
+            .roots
+            .iter()
+            .filter_map(|&id| self.node(id))
+            .flat_map(|root| root.iterations.iter().cloned())
+            .collect();
+        let iterations = iterations.len().max(1) as u64;
+        Some(Duration::from_nanos(node.overhead / iterations))
+    }
+
+    /// Returns a copy of this snapshot, where the scopes whose names
+    /// match `pattern` are combined into one scope called
+    /// `into_name`. A "*" in the pattern matches any amount of any
+    /// characters, eg. "entity_*" matches "entity_player" and
+    /// "entity_42". The combined scope has the samples of all the
+    /// matching scopes, and their children under it, where the
+    /// children with the same name are combined as well.
+    ///
+    /// If `across_parents` is false, only matching scopes with the
+    /// same parent are combined, in place of the first one of them.
+    /// If it's true, every matching scope is combined into one, which
+    /// is put under the closest scope all of them are under (or at
+    /// the root level, if there's no such scope). The time of the
+    /// moved scopes still counts in their original parents' times,
+    /// it's just not shown under them anymore.
+    ///
+    /// Matching scopes inside other matching scopes are moved along
+    /// with the outer one, instead of being combined.
+    ///
+    /// ```
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main", "entity_player"], &[1000])
+    ///     .add_scope(&["main", "entity_player", "update"], &[500])
+    ///     .add_scope(&["main", "entity_enemy"], &[2000, 2000])
+    ///     .add_scope(&["main", "entity_enemy", "update"], &[1000, 1000])
+    ///     .add_scope(&["main", "physics"], &[3000])
+    ///     .build()
+    ///     .merge_matching("entity_*", "entities", false);
+    /// let entities = snapshot.node_at_path(&["main", "entities"]).unwrap();
+    /// assert_eq!(entities.samples.len(), 3);
+    /// assert_eq!(entities.duration, Some(5000));
+    /// assert!(snapshot.node_at_path(&["main", "entity_enemy"]).is_none());
+    ///
+    /// let update = snapshot.node_at_path(&["main", "entities", "update"]).unwrap();
+    /// assert_eq!(snapshot.children_of(entities.id).len(), 1);
+    /// assert_eq!(update.samples.len(), 3);
+    /// assert_eq!(update.duration, Some(2500));
+    /// ```
+    pub fn merge_matching(
+        &self,
+        pattern: &str,
+        into_name: &str,
+        across_parents: bool,
+    ) -> ProfileSnapshot {
+        let mut nodes: HashMap<usize, ScopeNode> = self
+            .nodes
+            .iter()
+            .map(|node| (node.id, node.clone()))
+            .collect();
+        let mut roots = self.roots.clone();
+
+        // The matching scopes, grouped by the scope they're combined
+        // under.
+        let mut groups: Vec<(Option<usize>, Vec<usize>)> = Vec::new();
+        for id in self.find_outermost_matches(pattern) {
+            let parent = if across_parents {
+                None
+            } else {
+                self.node(id).and_then(|node| node.parent)
+            };
+            match groups.iter_mut().find(|group| group.0 == parent) {
+                Some(group) => group.1.push(id),
+                None => groups.push((parent, vec![id])),
+            }
+        }
+        if across_parents {
+            for group in &mut groups {
+                group.0 = self.closest_common_ancestor(&group.1);
+            }
+        }
+
+        for (target, ids) in groups {
+            // The combined scope takes the place and id of the first
+            // matching scope.
+            let id = ids[0];
+            let mut combined = nodes.remove(&id).unwrap();
+            for &other_id in &ids[1..] {
+                let mut other = nodes.remove(&other_id).unwrap();
+                get_children_mut(&mut nodes, &mut roots, other.parent).retain(|&i| i != other_id);
+                combined.children.append(&mut other.children);
+                combined.combine(other);
+            }
+            if combined.parent != target {
+                get_children_mut(&mut nodes, &mut roots, combined.parent).retain(|&i| i != id);
+                let anchor = self.ancestor_under(id, target);
+                let siblings = get_children_mut(&mut nodes, &mut roots, target);
+                let position = siblings
+                    .iter()
+                    .position(|&i| Some(i) == anchor)
+                    .unwrap_or(siblings.len());
+                siblings.insert(position, id);
+            }
+            for child in &combined.children {
+                if let Some(child) = nodes.get_mut(child) {
+                    child.parent = Some(id);
+                }
+            }
+            combined.name = into_name.to_string();
+            combined.parent = target;
+            nodes.insert(id, combined);
+            combine_same_name_children(&mut nodes, id);
+        }
+
+        // Rebuild the list, parents before children, with the depths
+        // of the moved scopes updated.
+        let mut ordered = Vec::new();
+        let mut stack: Vec<(usize, usize)> = roots.iter().rev().map(|&id| (id, 1)).collect();
+        while let Some((id, depth)) = stack.pop() {
+            if let Some(mut node) = nodes.remove(&id) {
+                node.depth = depth;
+                stack.extend(node.children.iter().rev().map(|&child| (child, depth + 1)));
+                ordered.push(node);
+            }
+        }
+        ProfileSnapshot {
+            thread_name: self.thread_name.clone(),
+            ..ProfileSnapshot::new(ordered, self.session_start)
+        }
+    }
+
+    /// Returns the ids of the scopes matching `pattern` which aren't
+    /// under other matching scopes, parents before children.
+    fn find_outermost_matches(&self, pattern: &str) -> Vec<usize> {
+        let mut matches = Vec::new();
+        let mut stack: Vec<usize> = self.roots.iter().rev().cloned().collect();
+        while let Some(id) = stack.pop() {
+            if let Some(node) = self.node(id) {
+                if matches_pattern(pattern, &node.name) {
+                    matches.push(id);
+                } else {
+                    stack.extend(node.children.iter().rev());
+                }
+            }
+        }
+        matches
+    }
+
+    /// Returns the ids of the scopes above the scope with the given
+    /// id, from the root level down to its parent.
+    fn ancestors_of(&self, id: usize) -> Vec<usize> {
+        let mut ancestors = Vec::new();
+        let mut current = self.parent_of(id);
+        while let Some(node) = current {
+            ancestors.push(node.id);
+            current = self.parent_of(node.id);
+        }
+        ancestors.reverse();
+        ancestors
+    }
+
+    /// Returns the closest scope all the given scopes are under,
+    /// `None` if they're only under the root level.
+    fn closest_common_ancestor(&self, ids: &[usize]) -> Option<usize> {
+        let mut common = self.ancestors_of(ids[0]);
+        for &id in &ids[1..] {
+            let ancestors = self.ancestors_of(id);
+            let shared = common
+                .iter()
+                .zip(&ancestors)
+                .take_while(|&(a, b)| a == b)
+                .count();
+            common.truncate(shared);
+        }
+        common.last().cloned()
+    }
+
+    /// Returns the scope directly under `ancestor` that is, or is
+    /// above, the scope with the given id.
+    fn ancestor_under(&self, id: usize, ancestor: Option<usize>) -> Option<usize> {
+        let mut current = self.node(id)?;
+        while current.parent != ancestor {
+            current = self.parent_of(current.id)?;
+        }
+        Some(current.id)
+    }
+
+    /// Compares the average sample of each scope in `current` to the
+    /// same scope in `baseline`, and returns the scopes which got
+    /// slower than `tolerance` allows, in the order of
+    /// [`nodes`](#method.nodes). The scopes are matched by their path
+    /// of names. Scopes that aren't in `baseline` (or have no samples
+    /// there) are returned as well, as their time is all new. Scopes
+    /// that were removed, or have no samples in `current`, are
+    /// ignored, as they can't be slower.
+    ///
+    /// This doesn't panic by itself, so an empty `Vec` means there
+    /// are no regressions, and the
+    /// [`Regression`](struct.Regression.html)s can be printed for a
+    /// readable failure message.
+    ///
+    /// ```
+    /// use stperf::{ProfileSnapshot, SnapshotBuilder, Tolerance};
+    ///
+    /// let baseline = SnapshotBuilder::new()
+    ///     .add_scope(&["main", "physics"], &[1_000_000])
+    ///     .add_scope(&["main", "rendering"], &[2_000_000])
+    ///     .build();
+    /// let current = SnapshotBuilder::new()
+    ///     .add_scope(&["main", "physics"], &[1_500_000])
+    ///     .add_scope(&["main", "rendering"], &[2_100_000])
+    ///     .build();
+    /// let regressions =
+    ///     ProfileSnapshot::assert_no_regression(&baseline, &current, Tolerance::Relative(0.1));
+    /// assert_eq!(regressions.len(), 1);
+    /// assert_eq!(
+    ///     regressions[0].to_string(),
+    ///     "main/physics: 1.000 ms -> 1.500 ms (+50.0%)"
+    /// );
+    /// ```
+    pub fn assert_no_regression(
+        baseline: &ProfileSnapshot,
+        current: &ProfileSnapshot,
+        tolerance: Tolerance,
+    ) -> Vec<Regression> {
+        let mut regressions = Vec::new();
+        for node in &current.nodes {
+            let average = match node.average_sample() {
+                Some(average) => average,
+                None => continue,
+            };
+            let path = current.path_of(node.id);
+            let path_refs: Vec<&str> = path.iter().map(|name| name.as_str()).collect();
+            let baseline_average = baseline
+                .node_at_path(&path_refs)
+                .and_then(|node| node.average_sample());
+            let regressed = match baseline_average {
+                Some(baseline_average) => match tolerance {
+                    Tolerance::Absolute(tolerance) => {
+                        average > baseline_average + tolerance.as_secs_f64() * 1_000_000_000.0
+                    }
+                    Tolerance::Relative(tolerance) => {
+                        average > baseline_average * (1.0 + tolerance)
+                    }
+                },
+                None => true,
+            };
+            if regressed {
+                regressions.push(Regression {
+                    path,
+                    baseline: baseline_average.map(|average| Duration::from_nanos(average as u64)),
+                    current: Duration::from_nanos(average as u64),
+                });
+            }
+        }
+        regressions
+    }
+
+    /// Returns a hash of the scopes' names, their places in the tree,
+    /// and their sample counts and durations. If the hash is the
+    /// same as before, the tree and its totals haven't changed, so
+    /// eg. a GUI can skip re-rendering it. The ids, the individual
+    /// samples and the iterations aren't hashed. The hash is only
+    /// stable within a single build of the program, so it shouldn't
+    /// be saved. To check the live measurements without taking a
+    /// snapshot, see [`tree_hash`](fn.tree_hash.html).
+    ///
+    /// ```
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let before = SnapshotBuilder::new()
+    ///     .add_scope(&["main", "physics"], &[1000])
+    ///     .build();
+    /// let same = SnapshotBuilder::new()
+    ///     .add_scope(&["main", "physics"], &[1000])
+    ///     .build();
+    /// let after = SnapshotBuilder::new()
+    ///     .add_scope(&["main", "physics"], &[1000, 1000])
+    ///     .build();
+    /// assert_eq!(before.tree_hash(), same.tree_hash());
+    /// assert_ne!(before.tree_hash(), after.tree_hash());
+    /// ```
+    pub fn tree_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash_scopes(&self.roots, &mut hasher);
+        hasher.finish()
+    }
+
+    /// Hashes the scopes with the given ids, and the scopes under
+    /// them, for `tree_hash`.
+    fn hash_scopes(&self, ids: &[usize], hasher: &mut DefaultHasher) {
+        // The count separates the siblings from the scopes under them.
+        ids.len().hash(hasher);
+        for node in ids.iter().filter_map(|&id| self.node(id)) {
+            node.name.hash(hasher);
+            node.samples.len().hash(hasher);
+            node.duration.hash(hasher);
+            self.hash_scopes(&node.children, hasher);
+        }
+    }
+
+    /// Returns the scopes as a nested
+    /// [RON](https://github.com/ron-rs/ron) structure. The scopes
+    /// have the same fields as [`ScopeNode`](struct.ScopeNode.html)s,
+    /// except the ids, as the children are nested inside their
+    /// parents. The `thread` field is the
+    /// [`thread_name`](#method.thread_name). The `version` field
+    /// tells which version of the format this is, in case the fields
+    /// change later.
+    ///
+    /// ```
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main"], &[3000])
+    ///     .add_scope(&["main", "physics"], &[1000])
+    ///     .build();
+    /// assert_eq!(snapshot.to_ron(), r#"(
+    ///     version: 1,
+    ///     thread: None,
+    ///     scopes: [
+    ///         (
+    ///             name: "main",
+    ///             depth: 1,
+    ///             samples: [3000],
+    ///             iterations: [0],
+    ///             overhead: 0,
+    ///             duration: Some(3000),
+    ///             rss_growth: None,
+    ///             lifetime_max: Some(3000),
+    ///             max_child_count: 1,
+    ///             children: [
+    ///                 (
+    ///                     name: "physics",
+    ///                     depth: 2,
+    ///                     samples: [1000],
+    ///                     iterations: [0],
+    ///                     overhead: 0,
+    ///                     duration: Some(1000),
+    ///                     rss_growth: None,
+    ///                     lifetime_max: Some(1000),
+    ///                     max_child_count: 0,
+    ///                     children: [],
+    ///                 ),
+    ///             ],
+    ///         ),
+    ///     ],
+    /// )
+    /// "#);
+    /// ```
+    pub fn to_ron(&self) -> String {
+        let mut ron = String::new();
+        ron += "(\n";
+        let _ = writeln!(ron, "    version: {},", EXPORT_VERSION);
+        let _ = writeln!(ron, "    thread: {:?},", self.thread_name);
+        ron += "    scopes: ";
+        self.write_ron_scopes(&self.roots, 1, &mut ron);
+        ron += ",\n)\n";
+        ron
+    }
+
+    /// Writes the scopes with the given ids as a RON list into `ron`,
+    /// indented by `indent` levels.
+    fn write_ron_scopes(&self, ids: &[usize], indent: usize, ron: &mut String) {
+        let nodes: Vec<&ScopeNode> = ids.iter().filter_map(|&id| self.node(id)).collect();
+        if nodes.is_empty() {
+            *ron += "[]";
+            return;
+        }
+        let outer = "    ".repeat(indent);
+        let inner = "    ".repeat(indent + 2);
+        *ron += "[\n";
+        for node in nodes {
+            let _ = writeln!(ron, "{}    (", outer);
+            let _ = writeln!(ron, "{}name: {:?},", inner, node.name);
+            let _ = writeln!(ron, "{}depth: {},", inner, node.depth);
+            let _ = writeln!(ron, "{}samples: {:?},", inner, node.samples);
+            let _ = writeln!(ron, "{}iterations: {:?},", inner, node.iterations);
+            let _ = writeln!(ron, "{}overhead: {},", inner, node.overhead);
+            let _ = writeln!(ron, "{}duration: {:?},", inner, node.duration);
+            let _ = writeln!(ron, "{}rss_growth: {:?},", inner, node.rss_growth);
+            let _ = writeln!(ron, "{}lifetime_max: {:?},", inner, node.lifetime_max);
+            let _ = writeln!(ron, "{}max_child_count: {},", inner, node.max_child_count);
+            let _ = write!(ron, "{}children: ", inner);
+            self.write_ron_scopes(&node.children, indent + 2, ron);
+            let _ = writeln!(ron, ",\n{}    ),", outer);
+        }
+        let _ = write!(ron, "{}]", outer);
+    }
+}
+
+/// Builds a [`ProfileSnapshot`](struct.ProfileSnapshot.html) out of
+/// known samples, instead of measuring them. Useful for testing code
+/// that consumes snapshots, as the output is fully reproducible.
+///
+/// ```
+/// # #[cfg(not(feature = "disabled"))] fn main() {
+/// use stperf::format::ReportOptions;
+/// use stperf::SnapshotBuilder;
+///
+/// let snapshot = SnapshotBuilder::new()
+///     .add_scope(&["main"], &[4_000_000, 4_000_000])
+///     .add_scope(&["main", "physics"], &[1_000_000, 3_000_000])
+///     .build();
+/// let output = stperf::get_formatted_snapshot(&snapshot, &ReportOptions::deterministic());
+/// assert_eq!(
+///     output,
+///     concat!(
+///         "╶──┬╼ main        - 100.0%,   4 ms/loop, 2 samples\n",
+///         "   └───╼ physics  -  50.0%,   2 ms/loop, 2 samples\n",
+///     )
+/// );
+/// # }
+/// # #[cfg(feature = "disabled")] fn main() {}
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotBuilder {
+    nodes: Vec<ScopeNode>,
+}
+
+impl SnapshotBuilder {
+    /// Creates a builder for an empty snapshot.
+    pub fn new() -> SnapshotBuilder {
+        SnapshotBuilder::default()
+    }
+
+    /// Adds `samples`, in nanoseconds, to the scope at the end of
+    /// `path`, which goes from a root-level scope to the scope
+    /// itself. The scopes along the path are created if they don't
+    /// exist yet, without any samples. Each sample is recorded in the
+    /// root iteration of its index in `samples`, also when samples are
+    /// added to the same scope again, so that adds more calls to the
+    /// same iterations instead of more iterations.
+    ///
+    /// ```
+    /// use stperf::SnapshotBuilder;
+    ///
+    /// let snapshot = SnapshotBuilder::new()
+    ///     .add_scope(&["main"], &[10_000, 10_000])
+    ///     .add_scope(&["main", "physics"], &[2000, 2000])
+    ///     .add_scope(&["main", "physics"], &[3000, 3000])
+    ///     .build();
+    /// let physics = snapshot.node_at_path(&["main", "physics"]).unwrap();
+    /// assert_eq!(physics.iterations, vec![0, 1, 0, 1]);
+    /// ```
+    pub fn add_scope(mut self, path: &[&str], samples: &[u64]) -> SnapshotBuilder {
+        let mut parent: Option<usize> = None;
+        for (depth, name) in path.iter().enumerate() {
+            let existing = self
+                .nodes
+                .iter()
+                .position(|node| node.parent == parent && node.name == *name);
+            let index = match existing {
+                Some(index) => index,
+                None => {
+                    let id = self.nodes.len() + 1;
+                    if let Some(parent) = parent {
+                        self.nodes[parent - 1].children.push(id);
+                    }
+                    self.nodes.push(ScopeNode {
+                        id,
+                        name: name.to_string(),
+                        depth: depth + 1,
+                        parent,
+                        children: Vec::new(),
+                        samples: Vec::new(),
+                        iterations: Vec::new(),
+                        overhead: 0,
+                        duration: None,
+                        rss_growth: None,
+                        lifetime_max: None,
+                        max_child_count: 0,
+                    });
+                    self.nodes.len() - 1
+                }
+            };
+            parent = Some(self.nodes[index].id);
+        }
+        if let Some(id) = parent {
+            let node = &mut self.nodes[id - 1];
+            node.samples.extend(samples);
+            node.iterations.extend(0..samples.len());
+            if !samples.is_empty() {
+                node.duration = Some(saturating_sum(node.samples.iter().cloned()));
+                node.lifetime_max = node.samples.iter().cloned().max();
+            }
+        }
+        self
+    }
+
+    /// Returns the built snapshot. The samples are considered to be
+    /// under the sample of their parent that's in the same root
+    /// iteration, for
+    /// [`ScopeNode::max_child_count`](struct.ScopeNode.html#structfield.max_child_count).
+    pub fn build(mut self) -> ProfileSnapshot {
+        for i in 0..self.nodes.len() {
+            let node = &self.nodes[i];
+            let max_child_count = node
+                .iterations
+                .iter()
+                .map(|iteration| {
+                    let children = node.children.iter().map(|&id| &self.nodes[id - 1]);
+                    let measured = |child: &&ScopeNode| child.iterations.contains(iteration);
+                    children.filter(measured).count()
+                })
+                .max();
+            self.nodes[i].max_child_count = max_child_count.unwrap_or(0);
+        }
+        ProfileSnapshot::new(self.nodes, None)
+    }
+}
+
+impl ScopeNode {
+    /// Returns true if the scope has no children, ie. it does the
+    /// actual work, instead of just wrapping other scopes.
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Returns the average duration of the samples, with the overhead
+    /// subtracted, in nanoseconds. `None` if there are no samples.
+    fn average_sample(&self) -> Option<f64> {
+        match self.duration {
+            Some(duration) if !self.samples.is_empty() => {
+                Some(duration as f64 / self.samples.len() as f64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Adds the samples and other measurements of `other` into this
+    /// scope. The children are left as they are.
+    pub(crate) fn combine(&mut self, other: ScopeNode) {
+        self.samples.extend(other.samples);
+        self.iterations.extend(other.iterations);
+        self.overhead = self.overhead.saturating_add(other.overhead);
+        self.duration = match (self.duration, other.duration) {
+            (Some(a), Some(b)) => Some(a.saturating_add(b)),
+            (a, b) => a.or(b),
+        };
+        self.rss_growth = self.rss_growth.max(other.rss_growth);
+        self.lifetime_max = self.lifetime_max.max(other.lifetime_max);
+        self.max_child_count = self.max_child_count.max(other.max_child_count);
+    }
+}
+
+/// How much slower a scope can get before it's a regression, see
+/// [`ProfileSnapshot::assert_no_regression`](struct.ProfileSnapshot.html#method.assert_no_regression).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tolerance {
+    /// The average sample can be this much longer than in the
+    /// baseline.
+    Absolute(Duration),
+    /// The average sample can be longer than in the baseline by this
+    /// fraction of the baseline, eg. 0.1 for 10%.
+    Relative(f64),
+}
+
+/// Defines which overheads are subtracted from a scope's
+/// [`duration`](struct.ScopeNode.html#structfield.duration), see
+/// [`set_overhead_policy`](fn.set_overhead_policy.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverheadPolicy {
+    /// Only the overhead of measuring the scope itself. The overhead
+    /// of the scopes under it is left in, and shows up as the
+    /// scope's self time.
+    Own,
+    /// The overhead of measuring the scope, and the scopes under it.
+    /// The overhead of a scope is subtracted from every scope around
+    /// it too, so it doesn't show up as self time anywhere. This is
+    /// the default.
+    Inclusive,
+}
+
+/// A scope that got slower than the tolerance allows, see
+/// [`ProfileSnapshot::assert_no_regression`](struct.ProfileSnapshot.html#method.assert_no_regression).
+/// Displayed as eg. "main/physics: 1.000 ms -> 1.500 ms (+50.0%)".
+#[derive(Clone, Debug, PartialEq)]
+pub struct Regression {
+    /// The path of names to the scope.
+    pub path: Vec<String>,
+    /// The average sample in the baseline, `None` if the scope is
+    /// new.
+    pub baseline: Option<Duration>,
+    /// The average sample in the compared snapshot.
+    pub current: Duration,
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let to_ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let path = self.path.join("/");
+        match self.baseline {
+            Some(baseline) => {
+                write!(
+                    f,
+                    "{}: {:.3} ms -> {:.3} ms",
+                    path,
+                    to_ms(baseline),
+                    to_ms(self.current)
+                )?;
+                if baseline > Duration::new(0, 0) {
+                    let change = to_ms(self.current) / to_ms(baseline) - 1.0;
+                    write!(f, " ({:+.1}%)", 100.0 * change)?;
+                }
+                Ok(())
+            }
+            None => write!(f, "{}: new scope, {:.3} ms", path, to_ms(self.current)),
+        }
+    }
+}
+
+/// Returns the sum of `values`, or `u64::MAX` if it doesn't fit.
+/// Durations are summed with this, so that the totals of very long
+/// sessions don't wrap around.
+pub(crate) fn saturating_sum<I: IntoIterator<Item = u64>>(values: I) -> u64 {
+    values.into_iter().fold(0, u64::saturating_add)
+}
+
+/// Combines the scopes under the scope `id` in `nodes` which have the
+/// same parent and name, recursively.
+fn combine_same_name_children(nodes: &mut HashMap<usize, ScopeNode>, id: usize) {
+    let children = match nodes.get(&id) {
+        Some(node) => node.children.clone(),
+        None => return,
+    };
+    let mut kept: Vec<usize> = Vec::new();
+    for child_id in children {
+        let same_name = match nodes.get(&child_id) {
+            Some(child) => kept
+                .iter()
+                .cloned()
+                .find(|kept| nodes[kept].name == child.name),
+            None => continue,
+        };
+        match same_name {
+            Some(first) => {
+                let mut other = nodes.remove(&child_id).unwrap();
+                for grandchild in &other.children {
+                    if let Some(grandchild) = nodes.get_mut(grandchild) {
+                        grandchild.parent = Some(first);
+                    }
+                }
+                let first = nodes.get_mut(&first).unwrap();
+                first.children.append(&mut other.children);
+                first.combine(other);
+            }
+            None => kept.push(child_id),
+        }
+    }
+    for &child in &kept {
+        combine_same_name_children(nodes, child);
+    }
+    nodes.get_mut(&id).unwrap().children = kept;
+}
+
+/// Returns the list of children of `parent` in `nodes`, or `roots`
+/// if `parent` is `None`.
+fn get_children_mut<'a>(
+    nodes: &'a mut HashMap<usize, ScopeNode>,
+    roots: &'a mut Vec<usize>,
+    parent: Option<usize>,
+) -> &'a mut Vec<usize> {
+    match parent.and_then(move |parent| nodes.get_mut(&parent)) {
+        Some(node) => &mut node.children,
+        None => roots,
+    }
+}
+
+/// Returns true if `name` matches `pattern`, where a "*" matches any
+/// amount of any characters.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if name.len() < first.len() + last.len() || !name.starts_with(first) || !name.ends_with(last) {
+        return false;
+    }
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}